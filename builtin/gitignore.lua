@@ -0,0 +1,19 @@
+local s = luaScript("gitignore")
+s.description = "adds a .gitignore for the project's language"
+s.version = "0.1.0"
+s.tags = {"git"}
+
+local ignores = {
+    rust = "/target\n",
+    node = "node_modules/\ndist/\n",
+    python = "__pycache__/\n*.pyc\n.venv/\n",
+}
+
+s.invoke_fn = function()
+    local language = prompt:select("language", "project language", {"rust", "node", "python"}, "rust")
+    local file = fs:openFile(DIR_PROJECT .. ".gitignore", "w")
+    file:write(ignores[language])
+    file:close()
+end
+
+scriptManager:add(s)
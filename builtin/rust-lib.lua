@@ -0,0 +1,39 @@
+local s = luaScript("rust-lib")
+s.description = "a rust library crate with a readme and a gitignore"
+s.version = "0.1.0"
+s.tags = {"rust"}
+s.params = {
+    { name = "name", description = "the crate name", required = true },
+    { name = "edition", default = "2021", choices = {"2015", "2018", "2021"} },
+}
+
+local files = {
+    ["Cargo.toml"] = [[
+[package]
+name = "{{ name | kebab_case }}"
+version = "0.1.0"
+edition = "{{ edition }}"
+
+[dependencies]
+]],
+    ["src/lib.rs"] = [[
+pub fn add(left: usize, right: usize) -> usize {
+    left + right
+}
+]],
+    ["README.md"] = "# {{ name }}\n",
+    [".gitignore"] = "/target\n",
+}
+
+s.invoke_fn = function(args)
+    if not fs:exists(DIR_PROJECT .. "src") then
+        fs:createDir(DIR_PROJECT .. "src")
+    end
+    for path, content in pairs(files) do
+        local file = fs:openFile(DIR_PROJECT .. path, "w")
+        file:write(template:render(content, args))
+        file:close()
+    end
+end
+
+scriptManager:add(s)
@@ -1,6 +1,9 @@
 print("test")
 
 local s = luaScript("pogger")
+s.description = "writes a file, lists the project and makes a request"
+s.version = "0.1.0"
+s.tags = {"example"}
 s.invoke_fn = function() 
 
     print("pog champ")
@@ -50,16 +53,19 @@ print(s)
 
 scriptManager:add(s)
 local s = luaScript("zip-test")
+s.description = "downloads curl's sources and unzips them"
+s.tags = {"example", "zip"}
 s.invoke_fn = function() 
 
     local z = http:request({
         url="https://github.com/curl/curl/releases/download/curl-7_83_1/curl-7.83.1.zip",
         method="get",
-        content_type="Text",
+        content_type="Bytes",
         headers={}
     });
     local file = fs:createFile(DIR_PROJECT .. "Curl.zip");
-    file:write(z.body.Text);
+    file:write(z.body.Bytes);
+    print(z.body.Bytes:sub(1, 4):toHex());
     print("done writing");
     pcall(function()
         fs:createDir(DIR_PROJECT .. "pog2/")
@@ -0,0 +1,156 @@
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+use clap_complete::Shell;
+
+use crate::lua::structures::{conflicts::ConflictPolicy, permissions::policy::PermissionPolicy};
+
+/// exit codes, usage errors exit with 2 like clap does
+pub mod exit {
+    pub const OK: i32 = 0;
+    // the script errored or something went wrong around it
+    pub const FAILURE: i32 = 1;
+    pub const USAGE: i32 = 2;
+    pub const SCRIPT_NOT_FOUND: i32 = 3;
+    pub const PERMISSION_DENIED: i32 = 4;
+    // existing files would have been changed
+    pub const CONFLICT: i32 = 5;
+    // a script failed to load, with --strict any script
+    pub const LOAD_FAILED: i32 = 6;
+    pub const INTERRUPTED: i32 = 130;
+}
+
+#[derive(Parser)]
+#[clap(author, version, about)]
+#[clap(after_help = "EXIT CODES:
+    0    success
+    1    the script or proj failed
+    2    bad usage or script arguments
+    3    the script couldn't be found
+    4    the script's permissions were refused
+    5    existing files would have been changed
+    6    a script failed to load
+    130  interrupted, changes were rolled back")]
+pub struct ProgramArgs {
+    /// give up when any script fails to load instead of leaving it out
+    #[clap(long, global = true)]
+    pub strict: bool,
+    #[clap(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// run a script on a project dir
+    Run {
+        script: String,
+        #[clap(parse(from_os_str))]
+        project_path: PathBuf,
+        /// script arguments, written as key=value
+        #[clap(value_name = "KEY=VALUE")]
+        trailing_args: Vec<String>,
+        #[clap(flatten)]
+        options: RunOptions,
+    },
+    /// show the loaded scripts
+    List {
+        /// only scripts with this tag
+        #[clap(long)]
+        tag: Option<String>,
+        #[clap(long)]
+        json: bool,
+    },
+    /// show a script's metadata, source, parameters and permissions
+    Info {
+        script: String,
+        #[clap(long)]
+        json: bool,
+    },
+    /// pick a script and a project dir interactively
+    New {
+        #[clap(flatten)]
+        options: RunOptions,
+    },
+    /// proj's own configuration
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+    /// the permissions remembered for each script
+    Permissions {
+        #[clap(subcommand)]
+        command: PermissionsCommand,
+    },
+    /// manage the installed scripts
+    Script {
+        #[clap(subcommand)]
+        command: ScriptCommand,
+    },
+    /// print a shell completion script
+    Completions {
+        #[clap(arg_enum)]
+        shell: Shell,
+    },
+}
+
+#[derive(Args)]
+pub struct RunOptions {
+    /// how permission requests are decided, defaults to interactive when run from a terminal, deny otherwise
+    #[clap(long, arg_enum)]
+    pub permission_policy: Option<PermissionPolicy>,
+    /// rules used by the policy-file permission policy
+    #[clap(long, parse(from_os_str))]
+    pub policy_file: Option<PathBuf>,
+    /// an argument for the script, written as key=value
+    #[clap(short, long = "arg", value_name = "KEY=VALUE", multiple_occurrences = true)]
+    pub args: Vec<String>,
+    /// never ask questions, prompts have to be answered with --arg
+    #[clap(long)]
+    pub non_interactive: bool,
+    /// record what the script would change without touching the disk or network
+    #[clap(long)]
+    pub dry_run: bool,
+    /// what to do with files which already exist, defaults to interactive when run from a terminal, overwrite otherwise
+    #[clap(long, arg_enum)]
+    pub on_conflict: Option<ConflictPolicy>,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// print the configuration
+    Show,
+    /// open config.toml in $VISUAL or $EDITOR
+    Edit,
+    /// print the config dir
+    Path,
+}
+
+#[derive(Subcommand)]
+pub enum PermissionsCommand {
+    /// show the remembered permissions, of every script or only one
+    List { script: Option<String> },
+    /// forget a script's permissions, or only the rules with the given value
+    Revoke {
+        script: String,
+        rule: Option<String>,
+    },
+    /// forget every remembered permission
+    Reset,
+}
+
+#[derive(Subcommand)]
+pub enum ScriptCommand {
+    /// install a script from a file, a url or a git repository (git+<url>[#rev])
+    Add {
+        source: String,
+        /// defaults to the file or repository name
+        #[clap(long)]
+        name: Option<String>,
+    },
+    /// uninstall a script
+    Remove { name: String },
+    /// fetch a script again from where it was installed, or every script
+    Update { name: Option<String> },
+    /// show the installed scripts
+    List,
+}
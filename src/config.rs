@@ -0,0 +1,40 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::lua::structures::{conflicts::ConflictPolicy, permissions::policy::PermissionPolicy};
+
+/// user configuration, read from `<config>/config.toml`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Config {
+    pub permission_policy: Option<PermissionPolicy>,
+    // defaults to `<config>/policy.toml`
+    pub policy_file: Option<PathBuf>,
+    // used when neither the command line nor the script picks one
+    pub on_conflict: Option<ConflictPolicy>,
+    // false disables every built-in script
+    pub builtins: Option<bool>,
+    // built-in scripts to leave out, without the `builtin:` prefix
+    pub disabled_builtins: Vec<String>,
+}
+
+impl Config {
+    pub fn path(config_dir: &Path) -> PathBuf {
+        config_dir.join("config.toml")
+    }
+
+    pub fn load(config_dir: &Path) -> Self {
+        let path = Self::path(config_dir);
+        match fs::read_to_string(&path) {
+            Ok(content) => toml::from_str(&content).unwrap_or_else(|e| {
+                eprintln!("unable to parse {} : {}", path.display(), e);
+                Config::default()
+            }),
+            Err(_) => Config::default(),
+        }
+    }
+}
@@ -0,0 +1,33 @@
+use mlua::Lua;
+
+use crate::config::Config;
+
+use super::{load_error::LoadError, methods::load_script, structures::scripts::SCRIPTS_MANAGER};
+
+/// names of built-in scripts start with this, user scripts can't use it
+pub const PREFIX: &str = "builtin:";
+
+/// scripts shipped inside the binary, by file name
+pub const BUILTINS: &[(&str, &str)] = &[
+    ("gitignore", include_str!("../../builtin/gitignore.lua")),
+    ("rust-lib", include_str!("../../builtin/rust-lib.lua")),
+];
+
+/// loads the built-in scripts the config doesn't disable.
+/// a user script with the same name (without the prefix) takes precedence when running.
+pub fn load_builtins(lua: &Lua, config: &Config) -> Vec<LoadError> {
+    let mut errors = Vec::new();
+    if !config.builtins.unwrap_or(true) {
+        return errors;
+    }
+    for (name, code) in BUILTINS {
+        if config.disabled_builtins.iter().any(|x| x == name) {
+            continue;
+        }
+        SCRIPTS_MANAGER.lock().unwrap().loading_builtin = true;
+        let result = load_script(lua, code.to_string(), &format!("{}{}", PREFIX, name), None);
+        SCRIPTS_MANAGER.lock().unwrap().loading_builtin = false;
+        errors.extend(result.err());
+    }
+    errors
+}
@@ -0,0 +1,144 @@
+use core::fmt;
+
+use mlua::Error;
+
+/// a script file which couldn't be loaded, with where it went wrong when lua says so
+#[derive(Debug, Clone)]
+pub struct LoadError {
+    // the file, or `builtin:<name>`
+    pub name: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+    // the offending line
+    pub snippet: Option<String>,
+}
+
+/// the innermost message, callback errors wrap the one that matters
+fn message(e: &Error) -> String {
+    match e {
+        Error::CallbackError { cause, .. } => message(cause),
+        Error::SyntaxError { message, .. } => message.clone(),
+        e => e.to_string(),
+    }
+}
+
+/// the line of the first `<chunk>:<line>:` in `text`, and where the location ends.
+/// lua shortens long chunk names so only the line number is looked for.
+fn find_line(text: &str) -> Option<(usize, usize)> {
+    text.match_indices(':').find_map(|(i, _)| {
+        let rest = &text[i + 1..];
+        let digits = rest.split(':').next()?;
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        Some((digits.parse().ok()?, i + 1 + digits.len() + 1))
+    })
+}
+
+impl LoadError {
+    pub fn new(name: &str, message: String) -> Self {
+        LoadError {
+            name: name.to_string(),
+            line: None,
+            column: None,
+            message,
+            snippet: None,
+        }
+    }
+
+    pub fn from_lua(name: &str, code: &str, e: &Error) -> Self {
+        let mut message = message(e);
+        let line = match find_line(&message) {
+            Some((line, end)) => {
+                message = message[end..].trim_start().to_string();
+                Some(line)
+            }
+            // a callback error only has the position in its traceback
+            None => match e {
+                Error::CallbackError { traceback, .. } => find_line(traceback).map(|(l, _)| l),
+                _ => None,
+            },
+        };
+        let snippet = line.and_then(|l| code.lines().nth(l.checked_sub(1)?).map(|x| x.to_string()));
+        // syntax errors end with `near '<token>'`, lua has no columns
+        let column = snippet.as_ref().and_then(|s| {
+            let token = message.split("near '").nth(1)?.split('\'').next()?;
+            s.find(token).map(|c| c + 1)
+        });
+        LoadError {
+            name: name.to_string(),
+            line,
+            column,
+            message,
+            snippet,
+        }
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.message)?;
+        match (self.line, self.column) {
+            (Some(line), Some(column)) => write!(f, "  --> {}:{}:{}", self.name, line, column)?,
+            (Some(line), None) => write!(f, "  --> {}:{}", self.name, line)?,
+            _ => write!(f, "  --> {}", self.name)?,
+        }
+        if let (Some(line), Some(snippet)) = (self.line, &self.snippet) {
+            let gutter = " ".repeat(line.to_string().len());
+            write!(f, "\n{} |\n{} | {}", gutter, line, snippet)?;
+            if let Some(column) = self.column {
+                write!(f, "\n{} | {}^", gutter, " ".repeat(column - 1))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+#[cfg(test)]
+mod tests {
+    use mlua::Lua;
+
+    use super::*;
+
+    fn load(code: &str) -> LoadError {
+        let e = Lua::new()
+            .load(code)
+            .set_name("=my.lua")
+            .unwrap()
+            .exec()
+            .unwrap_err();
+        LoadError::from_lua("my.lua", code, &e)
+    }
+
+    #[test]
+    fn syntax_errors_point_at_the_token() {
+        let e = load("local a = 1\nlocal x = = 1\n");
+        assert_eq!(e.line, Some(2));
+        assert_eq!(e.column, Some(9));
+        assert!(e.message.contains("near '='"), "{}", e.message);
+        assert_eq!(e.snippet.as_deref(), Some("local x = = 1"));
+        assert!(e.to_string().ends_with("  --> my.lua:2:9\n  |\n2 | local x = = 1\n  |         ^"), "{}", e);
+    }
+
+    #[test]
+    fn runtime_errors_have_the_line() {
+        let e = load("local a = 1\n\nerror(\"boom\")\n");
+        assert_eq!(e.line, Some(3));
+        assert_eq!(e.column, None);
+        // lua may add a traceback after the message
+        assert!(e.message.starts_with("boom"), "{}", e.message);
+        assert_eq!(e.snippet.as_deref(), Some("error(\"boom\")"));
+        assert!(e.to_string().contains("  --> my.lua:3\n"), "{}", e);
+    }
+
+    #[test]
+    fn errors_without_a_location() {
+        assert_eq!(find_line("no location here"), None);
+        assert_eq!(find_line("[string \"...\"]:12: oops"), Some((12, 18)));
+        let e = LoadError::new("builtin:new", "unable to read".to_string());
+        assert_eq!(e.to_string(), "unable to read\n  --> builtin:new");
+    }
+}
@@ -1,8 +1,14 @@
-use mlua::{Lua, MultiValue};
+use std::path::Path;
 
-use crate::lua::utils::pretty_print_lvalue;
+use mlua::{Lua, MultiValue, Table, Value};
 
-use super::structures::scripts::{LuaScript, SCRIPTS_MANAGER};
+use crate::lua::{load_error::LoadError, utils::pretty_print_lvalue};
+
+use super::{builtin::PREFIX, structures::{
+    bytes::bytes_global,
+    permissions::store::hash_source,
+    scripts::{LuaScript, SCRIPTS_MANAGER},
+}};
 
 pub fn setup_lua(lua: &Lua) {
     let globals = lua.globals();
@@ -30,7 +36,17 @@ pub fn setup_lua(lua: &Lua) {
         .set(
             "luaScript",
             lua.create_function(|_, s: String| {
-                let p = LuaScript { name: s };
+                let manager = SCRIPTS_MANAGER.lock().unwrap();
+                let p = LuaScript {
+                    name: if manager.loading_builtin {
+                        format!("{}{}", PREFIX, s)
+                    } else {
+                        s
+                    },
+                    hash: manager.loading_hash.clone(),
+                    file: manager.loading_file.clone(),
+                    ..Default::default()
+                };
                 Ok(p)
             })
             .unwrap(),
@@ -39,12 +55,91 @@ pub fn setup_lua(lua: &Lua) {
     globals
         .set("scriptManager", SCRIPTS_MANAGER.clone())
         .unwrap();
+    globals
+        .set("Bytes", bytes_global(lua).unwrap())
+        .unwrap();
+    // every string shares one metatable, its `__index` is the original `string` table.
+    // hidden so one script can't change string methods for all the others
+    let string_meta: Table = lua
+        .load(r#"return getmetatable("")"#)
+        .eval()
+        .expect("strings have no metatable");
+    string_meta.set("__metatable", false).unwrap();
+}
+
+fn shallow_copy<'lua>(lua: &'lua Lua, t: &Table<'lua>) -> mlua::Result<Table<'lua>> {
+    let copy = lua.create_table()?;
+    for pair in t.clone().pairs::<Value, Value>() {
+        let (k, v) = pair?;
+        copy.raw_set(k, v)?;
+    }
+    Ok(copy)
 }
 
-pub fn load_script(lua: &Lua, code: String) {
-    let cnk = lua.load(&code);
-    match cnk.exec() {
-        Ok(_) => {}
-        Err(e) => println!("{}", e),
+/// a fresh global table for one script file, reads fall back to the shared globals.
+/// assignments stay in the file's own table so scripts can't clobber each other.
+/// library tables like `string` are copied so they can't be changed for other files either,
+/// and the metatable is hidden so the shared globals can't be reached through it.
+/// the string metatable is hidden by `setup_lua` for the same reason.
+fn script_env(lua: &Lua) -> mlua::Result<Table> {
+    let env = lua.create_table()?;
+    for pair in lua.globals().pairs::<Value, Value>() {
+        let (k, v) = pair?;
+        let is_g = matches!(&k, Value::String(s) if s.as_bytes() == b"_G");
+        if let Value::Table(t) = &v && !is_g {
+            env.raw_set(k, shallow_copy(lua, t)?)?;
+        }
+    }
+    let meta = lua.create_table()?;
+    meta.set("__index", lua.globals())?;
+    meta.set("__metatable", false)?;
+    env.set_metatable(Some(meta));
+    env.raw_set("_G", env.clone())?;
+    Ok(env)
+}
+
+/// runs a script file in its own environment, `file` is None for built-in scripts
+pub fn load_script(lua: &Lua, code: String, name: &str, file: Option<&Path>) -> Result<(), LoadError> {
+    {
+        let mut manager = SCRIPTS_MANAGER.lock().unwrap();
+        manager.loading_hash = hash_source(&code);
+        manager.loading_file = file.map(|f| f.to_path_buf());
+    }
+    script_env(lua)
+        .and_then(|env| {
+            // `=` makes lua show the name as it is in messages
+            lua.load(&code)
+                .set_name(&format!("={}", name))?
+                .set_environment(env)?
+                .exec()
+        })
+        .map_err(|e| LoadError::from_lua(name, &code, &e))
+}
+
+#[cfg(test)]
+mod tests {
+    use mlua::{LuaOptions, StdLib};
+
+    use super::*;
+
+    #[test]
+    fn files_cant_change_each_others_libraries() {
+        let lua = Lua::new_with(StdLib::STRING | StdLib::TABLE, LuaOptions::default()).unwrap();
+        setup_lua(&lua);
+        let aa = r#"
+            assert(getmetatable("") == false)
+            string.upper = function() return "PWNED" end
+            table.insert = nil
+            shared = true
+        "#;
+        assert!(load_script(&lua, aa.to_string(), "aa", None).is_ok());
+        let zz = r#"
+            assert(("hello"):upper() == "HELLO")
+            assert(string.upper("hello") == "HELLO")
+            assert(table.insert ~= nil)
+            assert(shared == nil)
+            assert(pcall(setmetatable, _G, {}) == false)
+        "#;
+        assert!(load_script(&lua, zz.to_string(), "zz", None).is_ok());
     }
 }
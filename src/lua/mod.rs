@@ -1,3 +1,5 @@
+pub mod builtin;
+pub mod load_error;
 pub mod methods;
 pub mod structures;
 pub mod utils;
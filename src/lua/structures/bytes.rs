@@ -0,0 +1,166 @@
+use mlua::{Error, Lua, MetaMethod, Table, UserData, Value};
+
+/// a byte buffer, lua strings can hold bytes too but can't be indexed or sliced by byte
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bytes(pub Vec<u8>);
+
+/// the bytes of a lua string, a `Bytes` or a table of integers
+pub fn bytes_of(value: &Value) -> mlua::Result<Vec<u8>> {
+    match value {
+        Value::String(s) => Ok(s.as_bytes().to_vec()),
+        Value::UserData(ud) => Ok(ud.borrow::<Bytes>()?.0.clone()),
+        Value::Table(t) => t.clone().sequence_values::<u8>().collect(),
+        _ => Err(Error::FromLuaConversionError {
+            from: value.type_name(),
+            to: "Bytes",
+            message: Some("expected a string, Bytes or a table of bytes".to_string()),
+        }),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> mlua::Result<Vec<u8>> {
+    let s = s.trim();
+    if !s.is_ascii() || s.len() % 2 != 0 {
+        return Err(Error::RuntimeError(format!("\"{}\" isn't valid hex", s)));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&s[i..i + 2], 16)
+                .map_err(|_| Error::RuntimeError(format!("\"{}\" isn't valid hex", s)))
+        })
+        .collect()
+}
+
+impl Bytes {
+    /// a 1-based, inclusive lua index to an offset, negative ones count from the end
+    fn offset(&self, i: i64) -> i64 {
+        if i < 0 {
+            self.0.len() as i64 + i
+        } else {
+            i - 1
+        }
+    }
+
+    /// `string.sub` for bytes
+    pub fn sub(&self, i: i64, j: Option<i64>) -> Bytes {
+        let len = self.0.len() as i64;
+        let start = self.offset(i).max(0);
+        let end = self.offset(j.unwrap_or(-1)).min(len - 1);
+        if start > end {
+            return Bytes::default();
+        }
+        Bytes(self.0[start as usize..=end as usize].to_vec())
+    }
+}
+
+impl UserData for Bytes {
+    fn add_fields<'lua, F: mlua::UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_meta_field_with("__name", |_lua| Ok("Bytes".to_string()));
+    }
+
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("len", |_l, t, ()| Ok(t.0.len()));
+        methods.add_method("sub", |_l, t, (i, j): (i64, Option<i64>)| Ok(t.sub(i, j)));
+        // 1-based like strings, nil when out of range
+        methods.add_method("get", |_l, t, i: i64| {
+            Ok(usize::try_from(i - 1).ok().and_then(|i| t.0.get(i).copied()))
+        });
+        methods.add_method("toString", |l, t, ()| l.create_string(&t.0));
+        methods.add_method("toHex", |_l, t, ()| Ok(to_hex(&t.0)));
+        methods.add_method("toBase64", |_l, t, ()| Ok(base64::encode(&t.0)));
+        methods.add_method("toTable", |_l, t, ()| Ok(t.0.clone()));
+
+        methods.add_meta_method(MetaMethod::Len, |_l, t, ()| Ok(t.0.len()));
+        // bytes[i] is the same as bytes:get(i), method names still work
+        methods.add_meta_method(MetaMethod::Index, |_l, t, key: Value| match key {
+            Value::Integer(i) => Ok(usize::try_from(i - 1).ok().and_then(|i| t.0.get(i).copied())),
+            _ => Ok(None),
+        });
+        methods.add_meta_method(MetaMethod::ToString, |l, t, ()| l.create_string(&t.0));
+        methods.add_meta_method(MetaMethod::Eq, |_l, t, other: Bytes| Ok(t.0 == other.0));
+        // either side may be a string
+        methods.add_meta_function(MetaMethod::Concat, |_l, (a, b): (Value, Value)| {
+            let mut bytes = bytes_of(&a)?;
+            bytes.extend(bytes_of(&b)?);
+            Ok(Bytes(bytes))
+        });
+    }
+}
+
+/// the `Bytes` global, makes buffers
+pub fn bytes_global(lua: &Lua) -> mlua::Result<Table> {
+    let t = lua.create_table()?;
+    t.set(
+        "new",
+        lua.create_function(|_, size: Option<usize>| Ok(Bytes(vec![0; size.unwrap_or(0)])))?,
+    )?;
+    t.set(
+        "from",
+        lua.create_function(|_, v: Value| Ok(Bytes(bytes_of(&v)?)))?,
+    )?;
+    t.set(
+        "fromHex",
+        lua.create_function(|_, s: String| Ok(Bytes(from_hex(&s)?)))?,
+    )?;
+    t.set(
+        "fromBase64",
+        lua.create_function(|_, s: String| {
+            base64::decode(s.trim())
+                .map(Bytes)
+                .map_err(|e| Error::RuntimeError(format!("invalid base64 : {}", e)))
+        })?,
+    )?;
+    Ok(t)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sub_is_like_string_sub() {
+        let b = Bytes(b"hello".to_vec());
+        assert_eq!(b.sub(2, Some(3)), Bytes(b"el".to_vec()));
+        assert_eq!(b.sub(-3, None), Bytes(b"llo".to_vec()));
+        assert_eq!(b.sub(0, Some(100)), b);
+        assert_eq!(b.sub(4, Some(2)), Bytes::default());
+        assert_eq!(Bytes::default().sub(1, None), Bytes::default());
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        assert_eq!(to_hex(&[0, 0x7f, 0xff]), "007fff");
+        assert_eq!(from_hex(" 007FFF ").unwrap(), vec![0, 0x7f, 0xff]);
+        assert!(from_hex("abc").is_err());
+        assert!(from_hex("zz").is_err());
+        assert!(from_hex("é0").is_err());
+    }
+
+    #[test]
+    fn bytes_in_lua() {
+        let lua = Lua::new();
+        lua.globals().set("Bytes", bytes_global(&lua).unwrap()).unwrap();
+        lua.load(
+            r#"
+            local b = Bytes.from("\0\1\255")
+            assert(#b == 3 and b:len() == 3)
+            assert(b[1] == 0 and b[3] == 255 and b[4] == nil and b:get(0) == nil)
+            assert(b:toHex() == "0001ff")
+            assert(Bytes.fromHex("0001ff") == b)
+            assert(Bytes.fromBase64(b:toBase64()) == b)
+            assert(Bytes.from({104, 105}):toString() == "hi")
+            assert(tostring("a" .. Bytes.from("b") .. "c") == "abc")
+            assert(#Bytes.new(4) == 4)
+            assert(pcall(Bytes.from, {256}) == false)
+            assert(pcall(Bytes.fromHex, "0") == false)
+            "#,
+        )
+        .exec()
+        .unwrap();
+    }
+}
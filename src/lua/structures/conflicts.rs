@@ -0,0 +1,334 @@
+use std::{fmt, io::IsTerminal, path::{Path, PathBuf}};
+
+use clap::ArgEnum;
+use serde::{Deserialize, Serialize};
+use similar::{ChangeTag, DiffTag, TextDiff};
+
+use super::permissions::prompt::TerminalPrompt;
+
+/// what happens to a file which was already there before the script changed it
+#[derive(ArgEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictPolicy {
+    /// keep the existing file
+    Skip,
+    /// keep what the script wrote
+    Overwrite,
+    /// keep what the script wrote, the existing file is saved next to it as `.orig`
+    Backup,
+    /// roll the whole run back
+    Fail,
+    /// show a diff and ask, existing and generated content can be merged
+    Interactive,
+}
+
+impl Default for ConflictPolicy {
+    /// without a terminal nobody can be asked, the script wins and proj warns about it
+    fn default() -> Self {
+        if std::io::stdin().is_terminal() && std::io::stderr().is_terminal() {
+            ConflictPolicy::Interactive
+        } else {
+            ConflictPolicy::Overwrite
+        }
+    }
+}
+
+/// how a conflict ended up being resolved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    Skipped,
+    Overwritten,
+    BackedUp(PathBuf),
+    // both sides' changes to the original, conflict markers where they overlap
+    Merged,
+    Failed,
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resolution::Skipped => write!(f, "kept the existing file"),
+            Resolution::Overwritten => write!(f, "overwritten"),
+            Resolution::BackedUp(p) => write!(f, "overwritten, backed up to {}", p.display()),
+            Resolution::Merged => write!(f, "merged, check the conflict markers"),
+            Resolution::Failed => write!(f, "refused"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ConflictError(pub Vec<PathBuf>);
+
+impl fmt::Display for ConflictError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let paths = self
+            .0
+            .iter()
+            .map(|p| format!("  - {}", p.display()))
+            .collect::<Vec<_>>();
+        write!(f, "Conflict Error (existing files would be changed :\n{})", paths.join("\n"))
+    }
+}
+
+impl std::error::Error for ConflictError {}
+
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".orig");
+    path.with_file_name(name)
+}
+
+/// `base[start..end]` replaced with `lines`
+struct Hunk<'a> {
+    start: usize,
+    end: usize,
+    lines: Vec<&'a str>,
+}
+
+/// the changes made to `base` to get `other`
+fn hunks<'a>(base: &'a str, other: &'a str) -> Vec<Hunk<'a>> {
+    let diff = TextDiff::from_lines(base, other);
+    let new_lines = diff.new_slices().to_vec();
+    diff.ops()
+        .iter()
+        .map(|op| op.as_tag_tuple())
+        .filter(|(tag, _, _)| *tag != DiffTag::Equal)
+        .map(|(_, old, new)| Hunk {
+            start: old.start,
+            end: old.end,
+            lines: new_lines[new].to_vec(),
+        })
+        .collect()
+}
+
+/// `base[start..end]` with the given hunks, which all fall inside it, applied
+fn apply(base: &[&str], start: usize, end: usize, hunks: &[&Hunk]) -> String {
+    let mut output = String::new();
+    let mut pos = start;
+    for hunk in hunks {
+        output.extend(base[pos..hunk.start].iter().copied());
+        output.extend(hunk.lines.iter().copied());
+        pos = hunk.end;
+    }
+    output.extend(base[pos..end].iter().copied());
+    output
+}
+
+/// both versions in full where they differ, git style
+fn mark_differences(existing: &str, generated: &str) -> String {
+    let mut output = String::new();
+    let mut ours = String::new();
+    let mut theirs = String::new();
+    for change in TextDiff::from_lines(existing, generated).iter_all_changes() {
+        match change.tag() {
+            ChangeTag::Equal => {
+                push_conflict(&mut output, &mut ours, &mut theirs);
+                output.push_str(change.value());
+            }
+            ChangeTag::Delete => ours.push_str(change.value()),
+            ChangeTag::Insert => theirs.push_str(change.value()),
+        }
+    }
+    push_conflict(&mut output, &mut ours, &mut theirs);
+    output
+}
+
+/// writes both sides between conflict markers, nothing if neither has anything
+fn push_conflict(output: &mut String, ours: &mut String, theirs: &mut String) {
+    if ours.is_empty() && theirs.is_empty() {
+        return;
+    }
+    for side in [&mut *ours, &mut *theirs] {
+        if !side.is_empty() && !side.ends_with('\n') {
+            side.push('\n');
+        }
+    }
+    output.push_str("<<<<<<< existing\n");
+    output.push_str(ours);
+    output.push_str("=======\n");
+    output.push_str(theirs);
+    output.push_str(">>>>>>> generated\n");
+    ours.clear();
+    theirs.clear();
+}
+
+/// a three way merge of what the file contained before the run (`base`), what it contains
+/// now (`existing`) and what the script made of it (`generated`).
+/// changes made by only one side are taken as they are, overlapping ones get git style
+/// conflict markers.
+/// when the file wasn't touched during the run the script's changes are all there is,
+/// every one of them is marked so nothing is taken silently.
+pub fn merge(base: &str, existing: &str, generated: &str) -> String {
+    if base == existing {
+        return mark_differences(existing, generated);
+    }
+    let base_lines = TextDiff::from_lines(base, base).old_slices().to_vec();
+    let ours = hunks(base, existing);
+    let theirs = hunks(base, generated);
+    let mut output = String::new();
+    let (mut i, mut j, mut pos) = (0, 0, 0);
+    while i < ours.len() || j < theirs.len() {
+        let start = match (ours.get(i), theirs.get(j)) {
+            (Some(a), Some(b)) => a.start.min(b.start),
+            (Some(a), None) => a.start,
+            (None, Some(b)) => b.start,
+            (None, None) => unreachable!(),
+        };
+        output.extend(base_lines[pos..start].iter().copied());
+        // grows while hunks of either side touch the region
+        let mut end = start;
+        let (mut in_ours, mut in_theirs) = (Vec::new(), Vec::new());
+        loop {
+            let mut grew = false;
+            while let Some(a) = ours.get(i) && a.start <= end {
+                end = end.max(a.end);
+                in_ours.push(a);
+                i += 1;
+                grew = true;
+            }
+            while let Some(b) = theirs.get(j) && b.start <= end {
+                end = end.max(b.end);
+                in_theirs.push(b);
+                j += 1;
+                grew = true;
+            }
+            if !grew {
+                break;
+            }
+        }
+        let mut existing_side = apply(&base_lines, start, end, &in_ours);
+        let mut generated_side = apply(&base_lines, start, end, &in_theirs);
+        if in_theirs.is_empty() || existing_side == generated_side {
+            output.push_str(&existing_side);
+        } else if in_ours.is_empty() {
+            output.push_str(&generated_side);
+        } else {
+            push_conflict(&mut output, &mut existing_side, &mut generated_side);
+        }
+        pos = end;
+    }
+    output.extend(base_lines[pos..].iter().copied());
+    output
+}
+
+/// decides what to do with `path`, asking the user with the interactive policy.
+/// `base` is what the file contained before the run.
+pub fn resolve(
+    policy: ConflictPolicy,
+    path: &Path,
+    base: &[u8],
+    existing: &[u8],
+    generated: &[u8],
+) -> Resolution {
+    match policy {
+        ConflictPolicy::Skip => Resolution::Skipped,
+        ConflictPolicy::Overwrite => Resolution::Overwritten,
+        ConflictPolicy::Backup => Resolution::BackedUp(backup_path(path)),
+        ConflictPolicy::Fail => Resolution::Failed,
+        ConflictPolicy::Interactive => ask(path, base, existing, generated),
+    }
+}
+
+fn ask(path: &Path, base: &[u8], existing: &[u8], generated: &[u8]) -> Resolution {
+    let text = match (
+        std::str::from_utf8(base),
+        std::str::from_utf8(existing),
+        std::str::from_utf8(generated),
+    ) {
+        (Ok(_), Ok(old), Ok(new)) => Some((old, new)),
+        _ => None,
+    };
+    eprintln!("{} already exists and was changed by the script.", path.display());
+    match text {
+        Some((old, new)) => {
+            let name = path.display().to_string();
+            eprint!(
+                "{}",
+                TextDiff::from_lines(old, new)
+                    .unified_diff()
+                    .header(&name, &name)
+            );
+        }
+        None => eprintln!("    binary content differs"),
+    }
+    let question = if text.is_some() {
+        "[o] overwrite, [s] skip, [b] backup, [m] merge, [f] fail : "
+    } else {
+        "[o] overwrite, [s] skip, [b] backup, [f] fail : "
+    };
+    loop {
+        let answer = match TerminalPrompt::read_line(question) {
+            Some(answer) => answer,
+            // stdin closed, nobody is there to answer
+            None => return Resolution::Failed,
+        };
+        match answer.as_str() {
+            "o" => return Resolution::Overwritten,
+            "s" => return Resolution::Skipped,
+            "b" => return Resolution::BackedUp(backup_path(path)),
+            "m" if text.is_some() => return Resolution::Merged,
+            "f" => return Resolution::Failed,
+            _ => eprintln!("unknown answer \"{}\"", answer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn untouched_files_mark_every_change() {
+        let base = "a\nb\nc\n";
+        assert_eq!(
+            merge(base, base, "a\nB\nc\nd\n"),
+            "a\n<<<<<<< existing\nb\n=======\nB\n>>>>>>> generated\nc\n<<<<<<< existing\n=======\nd\n>>>>>>> generated\n"
+        );
+    }
+
+    #[test]
+    fn changes_on_one_side_are_kept() {
+        let base = "a\nb\nc\nd\n";
+        assert_eq!(merge(base, "a\nB\nc\nd\n", "a\nb\nc\nD\n"), "a\nB\nc\nD\n");
+        assert_eq!(merge(base, "x\na\nb\nc\nd\n", base), "x\na\nb\nc\nd\n");
+    }
+
+    #[test]
+    fn same_change_on_both_sides() {
+        assert_eq!(merge("a\nb\n", "a\nB\n", "a\nB\n"), "a\nB\n");
+    }
+
+    #[test]
+    fn overlapping_changes_get_markers() {
+        assert_eq!(
+            merge("a\nb\nc\n", "a\nx\nc\n", "a\ny\nc\n"),
+            "a\n<<<<<<< existing\nx\n=======\ny\n>>>>>>> generated\nc\n"
+        );
+        // a side which removed the lines shows up empty
+        assert_eq!(
+            merge("a\nb", "a\n", "a\nc"),
+            "a\n<<<<<<< existing\n=======\nc\n>>>>>>> generated\n"
+        );
+    }
+
+    #[test]
+    fn new_file_on_both_sides() {
+        assert_eq!(merge("", "a\n", "a\n"), "a\n");
+        assert_eq!(
+            merge("", "a\n", "b\n"),
+            "<<<<<<< existing\na\n=======\nb\n>>>>>>> generated\n"
+        );
+    }
+
+    #[test]
+    fn policies_without_asking() {
+        let path = Path::new("/tmp/file");
+        assert_eq!(resolve(ConflictPolicy::Skip, path, b"", b"a", b"b"), Resolution::Skipped);
+        assert_eq!(resolve(ConflictPolicy::Overwrite, path, b"", b"a", b"b"), Resolution::Overwritten);
+        assert_eq!(resolve(ConflictPolicy::Fail, path, b"", b"a", b"b"), Resolution::Failed);
+        assert_eq!(
+            resolve(ConflictPolicy::Backup, path, b"", b"a", b"b"),
+            Resolution::BackedUp(backup_path(path))
+        );
+    }
+}
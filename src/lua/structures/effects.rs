@@ -0,0 +1,1415 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
+use similar::TextDiff;
+
+use super::conflicts::{self, ConflictError, ConflictPolicy, Resolution};
+
+/// something a script did (or would have done in a dry run) besides writing files
+#[derive(Debug, Clone)]
+pub enum Effect {
+    CreateDir(PathBuf),
+    Move(PathBuf, PathBuf),
+    Http(String, String),
+    Command(String),
+    Remove(PathBuf),
+    Chmod(PathBuf, u32),
+    // the target and the link
+    Symlink(PathBuf, PathBuf),
+    Touch(PathBuf),
+}
+
+/// how to undo one change made to the disk
+#[derive(Debug)]
+enum Undo {
+    // the file or dir didn't exist before
+    Remove(PathBuf),
+    // what the file contained before
+    Restore(PathBuf, Vec<u8>),
+    // moved from .0 to .1
+    Move(PathBuf, PathBuf),
+    // removed by moving it aside to .1, deleted for good on commit
+    Removed(PathBuf, PathBuf),
+    // the permissions it had before
+    Permissions(PathBuf, fs::Permissions),
+    // what a file contained before a command ran, unlike Restore never a conflict
+    Snapshot(PathBuf, Vec<u8>),
+    // a symlink and its target, writing replaces the link with a file
+    Link(PathBuf, PathBuf),
+}
+
+/// every filesystem mutation made by `LuaFs` / `LuaFile` goes through here.
+/// in a dry run nothing touches the disk, files are staged in memory instead so
+/// reading them back still works.
+/// otherwise files which were there before the run are staged the same way until the run is
+/// committed, so conflicts are resolved before they are overwritten. everything else goes
+/// straight to the disk, and the first change to every path is journaled so a failed run
+/// can be rolled back.
+#[derive(Debug, Default)]
+pub struct Effects {
+    pub dry_run: bool,
+    pub effects: Vec<Effect>,
+    // what every staged file contains
+    pub files: HashMap<PathBuf, Vec<u8>>,
+    // what a staged file contained before the run, None if it didn't exist. the merge base.
+    pub originals: HashMap<PathBuf, Option<Vec<u8>>>,
+    // dry run: files replaced as a whole rather than edited
+    pub overwritten: HashSet<PathBuf>,
+    // dry run: files and dirs which would exist afterwards
+    pub created: HashSet<PathBuf>,
+    pub dirs: HashSet<PathBuf>,
+    // dry run: files and dirs which would be removed, with everything under them
+    pub removed: HashSet<PathBuf>,
+    // newest last, emptied by `commit`
+    journal: Vec<Undo>,
+    journaled: HashSet<PathBuf>,
+    snapshotted: HashSet<PathBuf>,
+    // files and dirs the run created, writes to them are never conflicts
+    new_paths: HashSet<PathBuf>,
+    // staged files written out early so a command could see them
+    flushed: HashSet<PathBuf>,
+    // permissions and mtime given to staged files, applied on commit if they get written
+    metadata: HashMap<PathBuf, fs::Metadata>,
+}
+
+lazy_static::lazy_static! {
+    pub static ref EFFECTS : Arc<Mutex<Effects>> = Arc::new(Mutex::new(Effects { ..Default::default() }));
+}
+
+impl Effects {
+    /// brings `path` into the staging area, reading what is on disk the first time
+    fn stage(&mut self, path: &Path) -> io::Result<()> {
+        if !self.files.contains_key(path) {
+            let original = match fs::read(path) {
+                Ok(content) => Some(content),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e),
+            };
+            // a file removed earlier in the run, or under a removed dir, starts out empty
+            let removed = path.ancestors().any(|p| self.removed.contains(p));
+            self.removed.remove(path);
+            let content = if removed {
+                vec![]
+            } else {
+                original.clone().unwrap_or_default()
+            };
+            self.files.insert(path.to_path_buf(), content);
+            self.originals.insert(path.to_path_buf(), original);
+        }
+        Ok(())
+    }
+
+    fn is_new(&self, path: &Path) -> bool {
+        path.ancestors().any(|p| self.new_paths.contains(p))
+    }
+
+    /// whether changes to `path` are kept in memory rather than written
+    fn stages(&self, path: &Path) -> bool {
+        self.dry_run
+            || self.files.contains_key(path)
+            || (!self.is_new(path) && path.is_file())
+    }
+
+    /// puts a staged file on the disk early, it's still resolved on commit
+    fn flush(&mut self, path: &Path) -> io::Result<()> {
+        if self.dry_run {
+            return Ok(());
+        }
+        if let Some(content) = self.files.get(path).cloned() {
+            self.write_through(path, &content)?;
+            self.flushed.insert(path.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn write_through(&mut self, path: &Path, content: &[u8]) -> io::Result<()> {
+        self.journal(path)?;
+        write_atomic(path, content)
+    }
+
+    /// gives `path` the permissions and mtime of `meta`, the permissions are journaled
+    fn set_metadata(&mut self, path: &Path, meta: &fs::Metadata) -> io::Result<()> {
+        let current = fs::metadata(path)?.permissions();
+        self.journal.push(Undo::Permissions(path.to_path_buf(), current));
+        fs::set_permissions(path, meta.permissions())?;
+        filetime::set_file_mtime(path, filetime::FileTime::from_last_modification_time(meta))
+    }
+
+    /// writes a resolved file, with the metadata it was given during the run
+    fn write_resolved(&mut self, path: &Path, content: &[u8]) -> io::Result<()> {
+        self.write_through(path, content)?;
+        match self.metadata.remove(path) {
+            Some(meta) => self.set_metadata(path, &meta),
+            None => Ok(()),
+        }
+    }
+
+    /// forgets a staged path and everything under it
+    fn unstage(&mut self, path: &Path) {
+        self.metadata.retain(|p, _| !p.starts_with(path));
+        self.files.retain(|p, _| !p.starts_with(path));
+        self.originals.retain(|p, _| !p.starts_with(path));
+        self.flushed.retain(|p| !p.starts_with(path));
+    }
+
+    fn clear(&mut self) {
+        self.journal.clear();
+        self.journaled.clear();
+        self.snapshotted.clear();
+        self.new_paths.clear();
+        if !self.dry_run {
+            self.files.clear();
+            self.originals.clear();
+            self.flushed.clear();
+            self.metadata.clear();
+        }
+    }
+
+    /// remembers how to undo the first change made to `path`
+    fn journal(&mut self, path: &Path) -> io::Result<()> {
+        if self.dry_run || self.journaled.contains(path) {
+            return Ok(());
+        }
+        match fs::symlink_metadata(path) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                let target = fs::read_link(path)?;
+                self.journal.push(Undo::Link(path.to_path_buf(), target));
+            }
+            Ok(meta) if meta.is_file() => {
+                let content = fs::read(path)?;
+                self.journal.push(Undo::Restore(path.to_path_buf(), content));
+            }
+            // dirs which were already there are left alone
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                self.journal.push(Undo::Remove(path.to_path_buf()))
+            }
+            Err(e) => return Err(e),
+        }
+        self.journaled.insert(path.to_path_buf());
+        Ok(())
+    }
+
+    /// undoes every change since the last commit, newest first.
+    /// keeps going when something can't be undone and returns those errors.
+    pub fn rollback(&mut self) -> Vec<io::Error> {
+        let mut errors = Vec::new();
+        while let Some(undo) = self.journal.pop() {
+            let (path, result) = match &undo {
+                // a symlink to a dir is removed as a file
+                Undo::Remove(p) if is_real_dir(p) => (p, fs::remove_dir_all(p)),
+                Undo::Remove(p) => (p, fs::remove_file(p)),
+                Undo::Restore(p, content) => (p, fs::write(p, content)),
+                Undo::Move(from, to) => (to, fs::rename(to, from)),
+                Undo::Removed(p, aside) => (p, remove_any(p).and_then(|_| fs::rename(aside, p))),
+                Undo::Permissions(p, permissions) => (p, fs::set_permissions(p, permissions.clone())),
+                Undo::Snapshot(p, content) => (p, write_atomic(p, content)),
+                Undo::Link(p, target) => (p, remove_any(p).and_then(|_| make_symlink(target, p))),
+            };
+            match result {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => errors.push(io::Error::new(
+                    e.kind(),
+                    format!("{} : {}", path.display(), e),
+                )),
+                _ => {}
+            }
+        }
+        // staged files were never written
+        self.clear();
+        errors
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        if self.created.contains(path) || self.dirs.contains(path) {
+            return true;
+        }
+        !path.ancestors().any(|p| self.removed.contains(p)) && path.symlink_metadata().is_ok()
+    }
+
+    /// what a dry run would have changed, with diffs for text files
+    pub fn report(&self) -> String {
+        let mut output = String::from("dry run, nothing was changed\n");
+        for effect in self.effects.iter() {
+            match effect {
+                Effect::CreateDir(p) => output.push_str(&format!("create dir  {}\n", p.display())),
+                Effect::Move(from, to) => output.push_str(&format!(
+                    "move        {} -> {}\n",
+                    from.display(),
+                    to.display()
+                )),
+                Effect::Http(method, url) => output.push_str(&format!("http        {} {}\n", method, url)),
+                Effect::Command(c) => output.push_str(&format!("command     {}\n", c)),
+                Effect::Remove(p) => output.push_str(&format!("remove      {}\n", p.display())),
+                Effect::Chmod(p, mode) => {
+                    output.push_str(&format!("chmod       {:o} {}\n", mode, p.display()))
+                }
+                Effect::Symlink(target, link) => output.push_str(&format!(
+                    "symlink     {} -> {}\n",
+                    link.display(),
+                    target.display()
+                )),
+                Effect::Touch(p) => output.push_str(&format!("touch       {}\n", p.display())),
+            }
+        }
+        let mut paths = self.files.keys().collect::<Vec<_>>();
+        paths.sort();
+        for path in paths {
+            let new = &self.files[path];
+            let (action, old) = match &self.originals[path] {
+                None => ("create", &[][..]),
+                Some(old) if old == new => continue,
+                Some(old) if self.overwritten.contains(path) => ("overwrite", &old[..]),
+                Some(old) => ("modify", &old[..]),
+            };
+            output.push_str(&format!("{:<12}{}\n", action, path.display()));
+            match (std::str::from_utf8(old), std::str::from_utf8(new)) {
+                (Ok(old), Ok(new)) => {
+                    let name = path.display().to_string();
+                    output.push_str(
+                        &TextDiff::from_lines(old, new)
+                            .unified_diff()
+                            .header(&name, &name)
+                            .to_string(),
+                    );
+                }
+                _ => output.push_str("    binary content differs\n"),
+            }
+        }
+        output
+    }
+}
+
+pub fn is_dry_run() -> bool {
+    EFFECTS.lock().unwrap().dry_run
+}
+
+pub fn record(effect: Effect) {
+    EFFECTS.lock().unwrap().effects.push(effect);
+}
+
+/// keeps every change made so far, they can't be rolled back anymore.
+/// staged files which were there before are resolved with `policy` before they are written,
+/// on an error nothing is committed and the journal can still be rolled back.
+pub fn commit(policy: ConflictPolicy) -> io::Result<Vec<(PathBuf, Resolution)>> {
+    commit_with(|path, base, existing, generated| {
+        conflicts::resolve(policy, path, base, existing, generated)
+    })
+}
+
+/// `commit`, each conflict is decided by `resolve(path, base, existing, generated)`
+fn commit_with(
+    mut resolve: impl FnMut(&Path, &[u8], &[u8], &[u8]) -> Resolution,
+) -> io::Result<Vec<(PathBuf, Resolution)>> {
+    // copied out so the lock isn't held while asking the user
+    let staged = {
+        let effects = EFFECTS.lock().unwrap();
+        let mut staged = Vec::new();
+        if !effects.dry_run {
+            for (path, generated) in effects.files.iter() {
+                let base = effects.originals.get(path).cloned().flatten().unwrap_or_default();
+                staged.push((path.clone(), base, generated.clone(), effects.flushed.contains(path)));
+            }
+        }
+        staged
+    };
+    let mut rewrites = Vec::new();
+    let mut conflicts = Vec::new();
+    for (path, base, generated, flushed) in staged {
+        // what is there now, it may have been changed while the script ran.
+        // a flushed file has the script's content on disk already.
+        let existing = if flushed {
+            base.clone()
+        } else {
+            fs::read(&path).unwrap_or_else(|_| base.clone())
+        };
+        if generated == existing {
+            if flushed {
+                rewrites.push((path, generated));
+            }
+            continue;
+        }
+        let resolution = resolve(&path, &base, &existing, &generated);
+        conflicts.push((path, base, existing, generated, resolution));
+    }
+    let failed = conflicts
+        .iter()
+        .filter(|c| c.4 == Resolution::Failed)
+        .map(|c| c.0.clone())
+        .collect::<Vec<_>>();
+    if !failed.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            ConflictError(failed),
+        ));
+    }
+
+    let mut effects = EFFECTS.lock().unwrap();
+    for (path, content) in rewrites {
+        effects.write_resolved(&path, &content)?;
+    }
+    let mut resolved = Vec::new();
+    for (path, base, existing, generated, resolution) in conflicts {
+        match &resolution {
+            // only a flushed file has to be put back
+            Resolution::Skipped => {
+                if effects.flushed.contains(&path) {
+                    effects.write_through(&path, &existing)?;
+                }
+            }
+            Resolution::Overwritten => effects.write_resolved(&path, &generated)?,
+            Resolution::BackedUp(backup) => {
+                effects.write_through(backup, &existing)?;
+                effects.write_resolved(&path, &generated)?;
+            }
+            Resolution::Merged => {
+                let merged = conflicts::merge(
+                    &String::from_utf8_lossy(&base),
+                    &String::from_utf8_lossy(&existing),
+                    &String::from_utf8_lossy(&generated),
+                );
+                effects.write_resolved(&path, merged.as_bytes())?;
+            }
+            Resolution::Failed => {}
+        }
+        resolved.push((path, resolution));
+    }
+    for undo in effects.journal.iter() {
+        if let Undo::Removed(_, aside) = undo {
+            remove_any(aside)?;
+        }
+    }
+    effects.clear();
+    Ok(resolved)
+}
+
+/// writes next to `path` first and renames it into place, a crash never leaves half a file
+fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!(".{}.proj-tmp-{}", name, std::process::id()));
+    // the replacement keeps the mode of the file it replaces, e.g. an executable stays one
+    let permissions = fs::metadata(path).ok().map(|m| m.permissions());
+    let result = fs::write(&tmp, content)
+        .and_then(|_| match permissions {
+            Some(permissions) => fs::set_permissions(&tmp, permissions),
+            None => Ok(()),
+        })
+        .and_then(|_| fs::rename(&tmp, path));
+    if let Err(e) = result {
+        let _ = fs::remove_file(&tmp);
+        return Err(e);
+    }
+    Ok(())
+}
+
+fn is_real_dir(path: &Path) -> bool {
+    path.symlink_metadata().map_or(false, |m| m.is_dir())
+}
+
+/// removes a file, symlink or whole dir, nothing to do if it isn't there
+fn remove_any(path: &Path) -> io::Result<()> {
+    let result = if is_real_dir(path) {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        r => r,
+    }
+}
+
+/// also used after a panic, the lock may be poisoned then
+pub fn rollback() -> Vec<io::Error> {
+    EFFECTS.lock().unwrap_or_else(|e| e.into_inner()).rollback()
+}
+
+/// the content of `path` as the script sees it, staged changes included
+pub fn read(path: &Path) -> io::Result<Vec<u8>> {
+    let effects = EFFECTS.lock().unwrap();
+    if let Some(content) = effects.files.get(path) {
+        return Ok(content.clone());
+    }
+    if effects.dry_run && !effects.exists(path) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} doesn't exist", path.display()),
+        ));
+    }
+    drop(effects);
+    fs::read(path)
+}
+
+pub fn exists(path: &Path) -> bool {
+    let effects = EFFECTS.lock().unwrap();
+    if effects.dry_run {
+        effects.exists(path)
+    } else {
+        path.exists()
+    }
+}
+
+pub fn create_dir(path: &Path, all: bool) -> io::Result<()> {
+    let mut effects = EFFECTS.lock().unwrap();
+    if !effects.dry_run {
+        if !all {
+            effects.journal(path)?;
+            fs::create_dir(path)?;
+            effects.new_paths.insert(path.to_path_buf());
+            return Ok(());
+        }
+        // outermost first so the undo removes the whole new tree
+        let missing = path
+            .ancestors()
+            .take_while(|p| !p.exists())
+            .map(|p| p.to_path_buf())
+            .collect::<Vec<_>>();
+        for p in missing.iter().rev() {
+            effects.journal(p)?;
+        }
+        fs::create_dir_all(path)?;
+        effects.new_paths.extend(missing);
+        return Ok(());
+    }
+    if !all && effects.exists(path) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", path.display()),
+        ));
+    }
+    if !effects.exists(path) {
+        effects.removed.remove(path);
+        effects.dirs.insert(path.to_path_buf());
+        effects.effects.push(Effect::CreateDir(path.to_path_buf()));
+    }
+    Ok(())
+}
+
+/// replaces the whole content of `path`, creating missing parent dirs
+pub fn write_file(path: &Path, content: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        create_dir(parent, true)?;
+    }
+    let mut effects = EFFECTS.lock().unwrap();
+    if !effects.stages(path) {
+        if path.symlink_metadata().is_err() {
+            effects.new_paths.insert(path.to_path_buf());
+        }
+        return effects.write_through(path, content);
+    }
+    effects.stage(path)?;
+    effects.files.insert(path.to_path_buf(), content.to_vec());
+    effects.created.insert(path.to_path_buf());
+    effects.overwritten.insert(path.to_path_buf());
+    Ok(())
+}
+
+pub fn rename(from: &Path, to: &Path) -> io::Result<()> {
+    let mut effects = EFFECTS.lock().unwrap();
+    if !effects.dry_run && effects.stages(to) && !is_real_dir(from) {
+        // moving onto an existing file is a write to it, resolved on commit like any other
+        let content = match effects.files.get(from) {
+            Some(content) => content.clone(),
+            None => fs::read(from)?,
+        };
+        effects.stage(to)?;
+        effects.files.insert(to.to_path_buf(), content);
+        drop(effects);
+        return remove(from, false);
+    }
+    if !effects.dry_run {
+        // the staged content moves along, `from` is gone afterwards
+        effects.flush(from)?;
+        effects.unstage(from);
+        if to.symlink_metadata().is_err() {
+            effects.new_paths.insert(to.to_path_buf());
+        }
+        effects.journal(to)?;
+        fs::rename(from, to)?;
+        effects
+            .journal
+            .push(Undo::Move(from.to_path_buf(), to.to_path_buf()));
+        return Ok(());
+    }
+    if from.is_file() || effects.files.contains_key(from) {
+        effects.stage(from)?;
+        let content = effects.files[from].clone();
+        effects.stage(to)?;
+        effects.files.insert(to.to_path_buf(), content);
+        effects.created.insert(to.to_path_buf());
+        effects.overwritten.insert(to.to_path_buf());
+    }
+    // `from` is gone afterwards, like after `remove`
+    effects.files.retain(|p, _| !p.starts_with(from));
+    effects.originals.retain(|p, _| !p.starts_with(from));
+    effects.created.retain(|p| !p.starts_with(from));
+    effects.dirs.retain(|p| !p.starts_with(from));
+    effects.removed.insert(from.to_path_buf());
+    effects
+        .effects
+        .push(Effect::Move(from.to_path_buf(), to.to_path_buf()));
+    Ok(())
+}
+
+/// how many bytes of a command's dir are kept in memory so its changes can be rolled back
+const SNAPSHOT_LIMIT: u64 = 64 * 1024 * 1024;
+
+/// the paths under a command's dir before it ran
+pub struct Snapshot {
+    root: PathBuf,
+    paths: HashSet<PathBuf>,
+    // false when some files were too much to keep
+    pub complete: bool,
+}
+
+/// every path under `root`, symlinks aren't followed
+fn list_tree(root: &Path) -> Vec<(PathBuf, fs::Metadata)> {
+    let mut paths = Vec::new();
+    let mut stack = vec![root.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            let Ok(meta) = fs::symlink_metadata(&path) else {
+                continue;
+            };
+            if meta.is_dir() {
+                stack.push(path.clone());
+            }
+            paths.push((path, meta));
+        }
+    }
+    paths
+}
+
+/// commands write to the disk themselves, what they may change is recorded before they run.
+/// None in a dry run, commands aren't run then.
+pub fn before_command(root: &Path) -> io::Result<Option<Snapshot>> {
+    let mut effects = EFFECTS.lock().unwrap();
+    if effects.dry_run {
+        return Ok(None);
+    }
+    // commands can't see the staging area
+    let staged = effects.files.keys().cloned().collect::<Vec<_>>();
+    for path in staged {
+        effects.flush(&path)?;
+    }
+    let mut snapshot = Snapshot {
+        root: root.to_path_buf(),
+        paths: HashSet::new(),
+        complete: true,
+    };
+    let mut kept = effects
+        .journal
+        .iter()
+        .map(|u| match u {
+            Undo::Snapshot(_, content) => content.len() as u64,
+            _ => 0,
+        })
+        .sum::<u64>();
+    for (path, meta) in list_tree(root) {
+        if meta.is_file() && !effects.snapshotted.contains(&path) {
+            if kept + meta.len() > SNAPSHOT_LIMIT {
+                snapshot.complete = false;
+            } else if let Ok(content) = fs::read(&path) {
+                kept += content.len() as u64;
+                effects.journal.push(Undo::Snapshot(path.clone(), content));
+                effects.snapshotted.insert(path.clone());
+            }
+        }
+        snapshot.paths.insert(path);
+    }
+    Ok(Some(snapshot))
+}
+
+/// journals what the command created so it's removed on a rollback
+pub fn after_command(snapshot: Snapshot) {
+    let mut effects = EFFECTS.lock().unwrap();
+    let created = list_tree(&snapshot.root)
+        .into_iter()
+        .map(|(p, _)| p)
+        .filter(|p| !snapshot.paths.contains(p))
+        .collect::<HashSet<_>>();
+    for path in created.iter() {
+        // removing the outermost new dir takes everything under it along
+        let parent_is_new = path.parent().map_or(false, |p| created.contains(p));
+        if !parent_is_new {
+            effects.journal.push(Undo::Remove(path.clone()));
+            effects.new_paths.insert(path.clone());
+        }
+    }
+}
+
+/// removes a file or symlink, or a dir with `recursive` or when it is empty.
+/// the original is moved aside rather than deleted until the run is committed.
+pub fn remove(path: &Path, recursive: bool) -> io::Result<()> {
+    let mut effects = EFFECTS.lock().unwrap();
+    if !effects.exists(path) {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} not found", path.display()),
+        ));
+    }
+    let is_dir = if effects.dry_run {
+        effects.dirs.contains(path) || (!effects.created.contains(path) && is_real_dir(path))
+    } else {
+        is_real_dir(path)
+    };
+    if is_dir && !recursive {
+        let empty = if effects.dry_run {
+            let staged = effects
+                .created
+                .iter()
+                .chain(effects.dirs.iter())
+                .any(|p| p != path && p.starts_with(path));
+            let on_disk = match fs::read_dir(path) {
+                Ok(mut entries) => entries.any(|e| {
+                    e.map_or(true, |e| !effects.removed.contains(&e.path()))
+                }),
+                Err(_) => false,
+            };
+            !staged && !on_disk
+        } else {
+            fs::read_dir(path)?.next().is_none()
+        };
+        if !empty {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("{} isn't empty", path.display()),
+            ));
+        }
+    }
+    if !effects.dry_run {
+        effects.unstage(path);
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        // the journal length keeps the same path removed twice from colliding
+        let aside = path.with_file_name(format!(
+            ".{}.proj-removed-{}-{}",
+            name,
+            std::process::id(),
+            effects.journal.len()
+        ));
+        fs::rename(path, &aside)?;
+        effects
+            .journal
+            .push(Undo::Removed(path.to_path_buf(), aside));
+        return Ok(());
+    }
+    effects.files.retain(|p, _| !p.starts_with(path));
+    effects.originals.retain(|p, _| !p.starts_with(path));
+    effects.created.retain(|p| !p.starts_with(path));
+    effects.dirs.retain(|p| !p.starts_with(path));
+    effects.removed.insert(path.to_path_buf());
+    effects.effects.push(Effect::Remove(path.to_path_buf()));
+    Ok(())
+}
+
+/// sets the unix permission bits, elsewhere only the write bit means anything
+pub fn chmod(path: &Path, mode: u32) -> io::Result<()> {
+    let mut effects = EFFECTS.lock().unwrap();
+    if effects.dry_run {
+        if !effects.exists(path) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} not found", path.display()),
+            ));
+        }
+        effects.effects.push(Effect::Chmod(path.to_path_buf(), mode));
+        return Ok(());
+    }
+    let mut permissions = fs::metadata(path)?.permissions();
+    effects
+        .journal
+        .push(Undo::Permissions(path.to_path_buf(), permissions.clone()));
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        permissions.set_mode(mode);
+    }
+    #[cfg(not(unix))]
+    permissions.set_readonly(mode & 0o200 == 0);
+    fs::set_permissions(path, permissions)
+}
+
+/// creates `link` pointing to `target`, which doesn't have to exist
+pub fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    let mut effects = EFFECTS.lock().unwrap();
+    if effects.exists(link) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", link.display()),
+        ));
+    }
+    if effects.dry_run {
+        effects.removed.remove(link);
+        effects.created.insert(link.to_path_buf());
+        effects
+            .effects
+            .push(Effect::Symlink(target.to_path_buf(), link.to_path_buf()));
+        return Ok(());
+    }
+    effects.journal(link)?;
+    effects.new_paths.insert(link.to_path_buf());
+    make_symlink(target, link)
+}
+
+/// a relative `target` is relative to the link's dir
+fn make_symlink(target: &Path, link: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(target, link);
+    #[cfg(windows)]
+    {
+        let resolved = link.parent().map_or(target.to_path_buf(), |p| p.join(target));
+        if resolved.is_dir() {
+            std::os::windows::fs::symlink_dir(target, link)
+        } else {
+            std::os::windows::fs::symlink_file(target, link)
+        }
+    }
+}
+
+/// creates an empty file, or sets the mtime of an existing one to now
+pub fn touch(path: &Path) -> io::Result<()> {
+    if !exists(path) {
+        return write_file(path, &[]);
+    }
+    if is_dry_run() {
+        record(Effect::Touch(path.to_path_buf()));
+        return Ok(());
+    }
+    filetime::set_file_mtime(path, filetime::FileTime::now())
+}
+
+/// what `fs:stat` returns, symlinks aren't followed
+#[derive(Serialize, Debug, Clone)]
+pub struct Stat {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    // 0 for dirs
+    pub size: u64,
+    // seconds since the epoch
+    pub modified: Option<u64>,
+    // the unix permission bits
+    pub mode: Option<u32>,
+    pub readonly: bool,
+}
+
+/// `path` as the run sees it, staged in a dry run. None if it doesn't exist.
+pub fn stat(path: &Path) -> io::Result<Option<Stat>> {
+    let effects = EFFECTS.lock().unwrap();
+    if !effects.exists(path) {
+        return Ok(None);
+    }
+    let staged = effects.files.get(path);
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => Some(meta),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    let Some(meta) = meta else {
+        // only exists in a dry run
+        let is_dir = effects.dirs.contains(path);
+        return Ok(Some(Stat {
+            kind: if is_dir { "dir" } else { "file" },
+            size: staged.map_or(0, |c| c.len() as u64),
+            modified: None,
+            mode: None,
+            readonly: false,
+        }));
+    };
+    #[cfg(unix)]
+    let mode = {
+        use std::os::unix::fs::PermissionsExt;
+        Some(meta.permissions().mode() & 0o7777)
+    };
+    #[cfg(not(unix))]
+    let mode = None;
+    Ok(Some(Stat {
+        kind: if meta.file_type().is_symlink() {
+            "symlink"
+        } else if meta.is_dir() {
+            "dir"
+        } else {
+            "file"
+        },
+        size: match staged {
+            Some(content) => content.len() as u64,
+            None if meta.is_dir() => 0,
+            None => meta.len(),
+        },
+        modified: meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs()),
+        mode,
+        readonly: meta.permissions().readonly(),
+    }))
+}
+
+/// gives `to` the permissions and mtime of `from`, nothing to do in a dry run
+pub fn copy_metadata(from: &Path, to: &Path) -> io::Result<()> {
+    let mut effects = EFFECTS.lock().unwrap();
+    if effects.dry_run {
+        return Ok(());
+    }
+    let meta = fs::metadata(from)?;
+    // the file on disk is still the one from before the run, it may never be overwritten
+    if effects.files.contains_key(to) {
+        effects.metadata.insert(to.to_path_buf(), meta);
+        return Ok(());
+    }
+    effects.set_metadata(to, &meta)
+}
+
+/// how a file is opened, files can always be read
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OpenMode {
+    pub write: bool,
+    // every write goes to the end
+    pub append: bool,
+    pub create: bool,
+    // fails if the file exists
+    pub create_new: bool,
+    pub truncate: bool,
+}
+
+impl OpenMode {
+    /// read and write, the file has to exist
+    pub const READ_WRITE: OpenMode = OpenMode {
+        write: true,
+        append: false,
+        create: false,
+        create_new: false,
+        truncate: false,
+    };
+
+    /// the modes of lua's `io.open`, plus `x` for a new file
+    pub fn parse(mode: &str) -> Option<OpenMode> {
+        let rw = OpenMode::READ_WRITE;
+        Some(match mode.trim_end_matches('b') {
+            "r" => OpenMode::default(),
+            "r+" => rw,
+            "w" | "w+" => OpenMode {
+                create: true,
+                truncate: true,
+                ..rw
+            },
+            "a" | "a+" => OpenMode {
+                append: true,
+                create: true,
+                ..rw
+            },
+            "x" | "x+" => OpenMode {
+                create_new: true,
+                ..rw
+            },
+            _ => return None,
+        })
+    }
+}
+
+pub fn open_file(path: &Path, mode: OpenMode) -> io::Result<FileHandle> {
+    let mut effects = EFFECTS.lock().unwrap();
+    // staged files are read back from the staging area too
+    let virtual_file = effects.files.contains_key(path) || (mode.write && effects.stages(path));
+    if !effects.dry_run && !virtual_file {
+        // journaled when opened, the handle writes straight to the disk
+        if mode.write {
+            if path.symlink_metadata().is_err() {
+                effects.new_paths.insert(path.to_path_buf());
+            }
+            effects.journal(path)?;
+        }
+        return Ok(FileHandle::Real(
+            OpenOptions::new()
+                .read(true)
+                .write(mode.write && !mode.append)
+                .append(mode.append)
+                .create(mode.create)
+                .create_new(mode.create_new)
+                .truncate(mode.truncate)
+                .open(path)?,
+        ));
+    }
+    let exists = effects.exists(path);
+    if mode.create_new && exists {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", path.display()),
+        ));
+    }
+    if !mode.create && !mode.create_new && !exists {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} not found", path.display()),
+        ));
+    }
+    effects.stage(path)?;
+    if mode.create || mode.create_new {
+        effects.created.insert(path.to_path_buf());
+    }
+    if mode.truncate {
+        effects.files.insert(path.to_path_buf(), vec![]);
+        effects.overwritten.insert(path.to_path_buf());
+    }
+    Ok(FileHandle::Virtual(VirtualFile {
+        path: path.to_path_buf(),
+        pos: 0,
+        mode,
+    }))
+}
+
+/// a file staged in memory, see `Effects`
+#[derive(Debug)]
+pub struct VirtualFile {
+    path: PathBuf,
+    pos: u64,
+    mode: OpenMode,
+}
+
+impl VirtualFile {
+    fn with<T>(&self, f: impl FnOnce(&mut Vec<u8>) -> T) -> T {
+        let mut effects = EFFECTS.lock().unwrap();
+        f(effects.files.entry(self.path.clone()).or_default())
+    }
+
+    fn check_writable(&self) -> io::Result<()> {
+        if self.mode.write {
+            Ok(())
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("{} was opened read only", self.path.display()),
+            ))
+        }
+    }
+}
+
+impl Read for VirtualFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let pos = self.pos as usize;
+        let read = self.with(|data| {
+            // a seek or truncate can leave the position past the end, nothing to read there
+            let start = pos.min(data.len());
+            let n = (data.len() - start).min(buf.len());
+            buf[..n].copy_from_slice(&data[start..start + n]);
+            n
+        });
+        self.pos += read as u64;
+        Ok(read)
+    }
+}
+
+impl Write for VirtualFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check_writable()?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.mode.append {
+            self.pos = self.with(|data| data.len()) as u64;
+        }
+        let pos = self.pos as usize;
+        self.with(|data| {
+            // past the end the gap is filled with zeros, like a real file
+            if data.len() < pos + buf.len() {
+                data.resize(pos + buf.len(), 0);
+            }
+            data[pos..pos + buf.len()].copy_from_slice(buf);
+        });
+        self.pos += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for VirtualFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let len = self.with(|data| data.len()) as i64;
+        let new = match pos {
+            SeekFrom::Start(p) => p as i64,
+            SeekFrom::End(p) => len + p,
+            SeekFrom::Current(p) => self.pos as i64 + p,
+        };
+        if new < 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to a negative position",
+            ));
+        }
+        self.pos = new as u64;
+        Ok(self.pos)
+    }
+}
+
+/// the handle behind a `LuaFile`
+#[derive(Debug)]
+pub enum FileHandle {
+    Real(File),
+    Virtual(VirtualFile),
+}
+
+impl FileHandle {
+    pub fn set_len(&mut self, len: u64) -> io::Result<()> {
+        match self {
+            FileHandle::Real(f) => f.set_len(len),
+            FileHandle::Virtual(f) => {
+                f.check_writable()?;
+                f.with(|data| data.resize(len as usize, 0));
+                Ok(())
+            }
+        }
+    }
+
+    pub fn size(&self) -> io::Result<u64> {
+        match self {
+            FileHandle::Real(f) => Ok(f.metadata()?.len()),
+            FileHandle::Virtual(f) => Ok(f.with(|data| data.len()) as u64),
+        }
+    }
+
+    pub fn position(&self) -> io::Result<u64> {
+        match self {
+            FileHandle::Real(f) => {
+                // `&File` can seek without a mutable handle
+                let mut f: &File = f;
+                f.stream_position()
+            }
+            FileHandle::Virtual(f) => Ok(f.pos),
+        }
+    }
+}
+
+impl Read for FileHandle {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            FileHandle::Real(f) => f.read(buf),
+            FileHandle::Virtual(f) => f.read(buf),
+        }
+    }
+}
+
+impl Write for FileHandle {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            FileHandle::Real(f) => f.write(buf),
+            FileHandle::Virtual(f) => f.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            FileHandle::Real(f) => f.flush(),
+            FileHandle::Virtual(f) => f.flush(),
+        }
+    }
+}
+
+impl Seek for FileHandle {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            FileHandle::Real(f) => f.seek(pos),
+            FileHandle::Virtual(f) => f.seek(pos),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("proj-effects-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // tests which use the global `EFFECTS`, other modules' tests lock it too
+    pub(crate) static GLOBAL: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn virtual_files_past_the_end() {
+        let _global = GLOBAL.lock().unwrap_or_else(|e| e.into_inner());
+        let path = std::env::temp_dir().join("proj-effects-virtual");
+        EFFECTS.lock().unwrap().files.insert(path.clone(), b"hello".to_vec());
+        let mut file = VirtualFile {
+            path: path.clone(),
+            pos: 0,
+            mode: OpenMode::READ_WRITE,
+        };
+        file.seek(SeekFrom::Start(50)).unwrap();
+        let mut buf = [0; 8];
+        assert_eq!(file.read(&mut buf).unwrap(), 0);
+        let mut rest = Vec::new();
+        assert_eq!(file.read_to_end(&mut rest).unwrap(), 0);
+        assert_eq!(file.write(b"").unwrap(), 0);
+        assert_eq!(EFFECTS.lock().unwrap().files[&path].len(), 5);
+
+        file.write_all(b"!").unwrap();
+        let data = EFFECTS.lock().unwrap().files.remove(&path).unwrap();
+        assert_eq!(data.len(), 51);
+        assert_eq!(&data[..5], b"hello");
+        assert!(data[5..50].iter().all(|b| *b == 0));
+        assert_eq!(data[50], b'!');
+
+        // truncated under the position
+        EFFECTS.lock().unwrap().files.insert(path.clone(), b"hello".to_vec());
+        let mut file = VirtualFile {
+            path: path.clone(),
+            pos: 4,
+            mode: OpenMode::READ_WRITE,
+        };
+        file.with(|data| data.truncate(1));
+        assert_eq!(file.read(&mut buf).unwrap(), 0);
+        EFFECTS.lock().unwrap().files.remove(&path);
+    }
+
+    /// `content` staged for the existing file `name`, then `on_disk` written behind the run's back
+    fn staged_conflict(name: &str, before: &str, content: &str, on_disk: Option<&str>) -> PathBuf {
+        let dir = temp_dir(name);
+        let path = dir.join("file");
+        fs::write(&path, before).unwrap();
+        EFFECTS.lock().unwrap().clear();
+        write_file(&path, content.as_bytes()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), before);
+        if let Some(on_disk) = on_disk {
+            fs::write(&path, on_disk).unwrap();
+        }
+        path
+    }
+
+    #[test]
+    fn commit_merges_changes_made_on_disk() {
+        let _global = GLOBAL.lock().unwrap_or_else(|e| e.into_inner());
+        let path = staged_conflict("merge", "a\nb\nc\nd\n", "a\nb\nc\nD\n", Some("a\nB\nc\nd\n"));
+        let resolved = commit_with(|_, base, existing, _| {
+            assert_eq!(base, b"a\nb\nc\nd\n");
+            assert_eq!(existing, b"a\nB\nc\nd\n");
+            Resolution::Merged
+        })
+        .unwrap();
+        assert_eq!(resolved, vec![(path.clone(), Resolution::Merged)]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\nB\nc\nD\n");
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn commit_marks_changes_to_untouched_files() {
+        let _global = GLOBAL.lock().unwrap_or_else(|e| e.into_inner());
+        let path = staged_conflict("mark", "a\nb\n", "a\nB\n", None);
+        commit_with(|_, _, _, _| Resolution::Merged).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "a\n<<<<<<< existing\nb\n=======\nB\n>>>>>>> generated\n"
+        );
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn commit_skips_and_fails() {
+        let _global = GLOBAL.lock().unwrap_or_else(|e| e.into_inner());
+        let skipped = staged_conflict("skip", "a\n", "b\n", Some("c\n"));
+        commit_with(|_, _, _, _| Resolution::Skipped).unwrap();
+        assert_eq!(fs::read_to_string(&skipped).unwrap(), "c\n");
+
+        let failed = staged_conflict("fail", "a\n", "b\n", None);
+        assert!(commit_with(|_, _, _, _| Resolution::Failed).is_err());
+        assert_eq!(fs::read_to_string(&failed).unwrap(), "a\n");
+        EFFECTS.lock().unwrap().rollback();
+        for path in [skipped, failed] {
+            fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn copied_metadata_waits_for_commit() {
+        use std::os::unix::fs::PermissionsExt;
+        let _global = GLOBAL.lock().unwrap_or_else(|e| e.into_inner());
+        let mode = |p: &Path| fs::metadata(p).unwrap().permissions().mode() & 0o7777;
+        for (name, resolution, expected) in [
+            ("meta-skip", Resolution::Skipped, 0o644),
+            ("meta-overwrite", Resolution::Overwritten, 0o755),
+        ] {
+            let path = staged_conflict(name, "a\n", "b\n", None);
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+            let source = path.with_file_name("source");
+            fs::write(&source, "b\n").unwrap();
+            fs::set_permissions(&source, fs::Permissions::from_mode(0o755)).unwrap();
+
+            copy_metadata(&source, &path).unwrap();
+            assert_eq!(mode(&path), 0o644);
+            commit_with(|_, _, _, _| resolution.clone()).unwrap();
+            assert_eq!(mode(&path), expected);
+            fs::remove_dir_all(path.parent().unwrap()).unwrap();
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn atomic_writes_keep_the_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = temp_dir("mode");
+        let path = dir.join("script.sh");
+        fs::write(&path, "echo a").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o750)).unwrap();
+        write_atomic(&path, b"echo b").unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o7777, 0o750);
+        assert_eq!(fs::read(&path).unwrap(), b"echo b");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn rollback_restores_symlinks() {
+        let dir = temp_dir("link");
+        let target = dir.join("target");
+        let link = dir.join("link");
+        fs::write(&target, "target").unwrap();
+        make_symlink(Path::new("target"), &link).unwrap();
+
+        let mut effects = Effects::default();
+        effects.write_through(&link, b"replaced").unwrap();
+        assert!(!link.symlink_metadata().unwrap().file_type().is_symlink());
+        assert!(effects.rollback().is_empty());
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("target"));
+        assert_eq!(fs::read(&target).unwrap(), b"target");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// a dry run on the global `EFFECTS`, reset when dropped even if the test fails
+    struct DryRun;
+
+    impl DryRun {
+        fn start() -> DryRun {
+            *EFFECTS.lock().unwrap_or_else(|e| e.into_inner()) = Effects { dry_run: true, ..Default::default() };
+            DryRun
+        }
+    }
+
+    impl Drop for DryRun {
+        fn drop(&mut self) {
+            *EFFECTS.lock().unwrap_or_else(|e| e.into_inner()) = Effects::default();
+        }
+    }
+
+    #[test]
+    fn dry_runs_change_nothing() {
+        let _global = GLOBAL.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = temp_dir("dry-run");
+        let existing = dir.join("existing");
+        fs::write(&existing, "a\nb\n").unwrap();
+        let _dry_run = DryRun::start();
+
+        create_dir(&dir.join("new"), false).unwrap();
+        write_file(&dir.join("new/file"), b"new").unwrap();
+        write_file(&existing, b"a\nB\n").unwrap();
+        rename(&existing, &dir.join("moved")).unwrap();
+        record(Effect::Http("GET".to_string(), "https://example.com".to_string()));
+
+        // the script sees its own changes
+        assert_eq!(read(&dir.join("new/file")).unwrap(), b"new");
+        assert_eq!(read(&dir.join("moved")).unwrap(), b"a\nB\n");
+        assert!(exists(&dir.join("new")));
+        assert!(!exists(&existing));
+        assert!(read(&existing).is_err());
+
+        // the disk doesn't
+        assert_eq!(fs::read(&existing).unwrap(), b"a\nb\n");
+        assert!(!dir.join("new").exists());
+        assert!(!dir.join("moved").exists());
+
+        let report = EFFECTS.lock().unwrap().report();
+        assert!(report.contains(&format!("create dir  {}", dir.join("new").display())));
+        assert!(report.contains(&format!("create      {}", dir.join("new/file").display())));
+        assert!(report.contains("http        GET https://example.com"));
+        assert!(report.contains("+B"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rollback_restores_files() {
+        let dir = temp_dir("rollback");
+        let existing = dir.join("existing");
+        let created = dir.join("new/file");
+        fs::write(&existing, "before").unwrap();
+
+        let mut effects = Effects::default();
+        effects.write_through(&existing, b"after").unwrap();
+        effects.write_through(&existing, b"again").unwrap();
+        effects.journal(&dir.join("new")).unwrap();
+        fs::create_dir(dir.join("new")).unwrap();
+        effects.write_through(&created, b"new").unwrap();
+        assert_eq!(fs::read(&existing).unwrap(), b"again");
+
+        assert!(effects.rollback().is_empty());
+        assert_eq!(fs::read(&existing).unwrap(), b"before");
+        assert!(!dir.join("new").exists());
+        // nothing left to undo
+        assert!(effects.journal.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rollback_drops_staged_files() {
+        let dir = temp_dir("staged");
+        let path = dir.join("file");
+        fs::write(&path, "on disk").unwrap();
+
+        let mut effects = Effects::default();
+        assert!(effects.stages(&path));
+        effects.stage(&path).unwrap();
+        assert_eq!(effects.originals[&path].as_deref(), Some(&b"on disk"[..]));
+        effects.files.insert(path.clone(), b"staged".to_vec());
+        effects.flush(&path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"staged");
+
+        assert!(effects.rollback().is_empty());
+        assert!(effects.files.is_empty());
+        assert_eq!(fs::read(&path).unwrap(), b"on disk");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn new_files_are_not_staged() {
+        let dir = temp_dir("new");
+        let path = dir.join("file");
+        let mut effects = Effects::default();
+        assert!(!effects.stages(&path));
+        effects.new_paths.insert(path.clone());
+        fs::write(&path, "written by the run").unwrap();
+        assert!(!effects.stages(&path));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn open_modes() {
+        let r = OpenMode::parse("r").unwrap();
+        assert!(!r.write && !r.create && !r.truncate);
+        let rw = OpenMode::parse("r+b").unwrap();
+        assert!(rw.write && !rw.create && !rw.truncate);
+        let w = OpenMode::parse("wb").unwrap();
+        assert!(w.write && w.create && w.truncate && !w.append);
+        let a = OpenMode::parse("a+").unwrap();
+        assert!(a.write && a.append && a.create && !a.truncate);
+        let x = OpenMode::parse("x").unwrap();
+        assert!(x.write && x.create_new);
+        assert!(OpenMode::parse("rw").is_none());
+        assert!(OpenMode::parse("").is_none());
+    }
+}
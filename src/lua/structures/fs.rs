@@ -3,14 +3,14 @@ use path_absolutize::*;
 use zip::{ZipArchive, write::FileOptions, ZipWriter};
 use core::fmt;
 use std::{
-    fs::{self, create_dir, read_dir, File, OpenOptions},
-    io::{Read, Seek, Write, Cursor},
+    fs::read_dir,
+    io::{Read, Seek, SeekFrom, Write, Cursor},
     path::{Path, PathBuf}, sync::Arc,
 };
 use std::error::Error as OtherError;
 use mlua::prelude::*;
 
-use crate::lua::structures::permissions::{PERMISSIONS_MANAGER, Permission};
+use crate::{lua::structures::{bytes::{bytes_of, Bytes}, effects::{self, FileHandle, OpenMode}, permissions::{FsAccess, PERMISSIONS_MANAGER, Permission}, template::{copy_template, BinaryFiles}, walk::{glob, walk, WalkOptions}}, utils::resolve_path};
 
 #[derive(Debug)]
 struct FsError(String);
@@ -25,98 +25,191 @@ impl OtherError for FsError {
 
 }
 
-pub struct LuaFs();
+/// the `fs` global, holds the project dir which relative globs start from
+pub struct LuaFs(pub String);
 
-pub struct LuaFile(pub String, pub File);
+/// the path and the handle, None once closed
+pub struct LuaFile(pub String, pub Option<FileHandle>);
 
 #[inline]
-fn is_path_allowed<T: Into<PathBuf>>(path: T) -> LuaResult<()> {
+pub(crate) fn is_path_allowed<T: Into<PathBuf>>(path: T, access: FsAccess) -> LuaResult<()> {
     let path: &PathBuf = &path.into();
     let mut permissions = PERMISSIONS_MANAGER.lock().unwrap();
-    let p = Permission::Fs(path.absolutize().unwrap().display().to_string());
+    // symlinks are followed first so they can't be used to escape an allowed dir
+    let p = Permission::Fs(access, resolve_path(path).display().to_string());
     permissions.ask_for_access(&p)
 
 
 } 
 
-enum FsBytesOrText {
-    Bytes(Vec<u8>),
-    Text(String),
+/// `is_path_allowed` for the entry itself rather than what it points to,
+/// only the dir it's in is resolved so a link is checked where it is
+pub(crate) fn is_entry_allowed(path: &Path, access: FsAccess) -> LuaResult<()> {
+    let resolved = match (path.parent(), path.file_name()) {
+        (Some(parent), Some(name)) => resolve_path(parent).join(name),
+        _ => resolve_path(path),
+    };
+    let p = Permission::Fs(access, resolved.display().to_string());
+    PERMISSIONS_MANAGER.lock().unwrap().ask_for_access(&p)
 }
+
+/// a mode written in octal digits, `755`, `"755"` and `"0o755"` are all 0o755
+pub(crate) fn parse_mode(digits: &str) -> Option<u32> {
+    u32::from_str_radix(digits.trim_start_matches("0o"), 8)
+        .ok()
+        .filter(|m| *m <= 0o7777)
+}
+
+/// writes a whole file after checking the path, creating missing parent dirs
+pub(crate) fn write_file(path: &Path, content: &[u8]) -> LuaResult<()> {
+    is_path_allowed(path, FsAccess::Write)?;
+    effects::write_file(path, content)?;
+    Ok(())
+}
+
+/// a string, `Bytes` or table of bytes given to `write`
+struct FsBytesOrText(Vec<u8>);
+
 impl<'lua> FromLua<'lua> for FsBytesOrText {
-    fn from_lua(lua_value: LuaValue<'lua>, lua: &'lua Lua) -> LuaResult<Self> {
-        match lua_value {
-            LuaValue::String(s) => Ok(FsBytesOrText::Text(s.to_string_lossy().to_string())),
-            LuaValue::Table(t) => {
-                let mut bytes = Vec::new();
-                for i in 1..=t.len().unwrap_or(0) {
-                    let v : u8 = t.get(i)?;
-                    bytes.push(v);
-                }
-                Ok(FsBytesOrText::Bytes(bytes))
-            }
-            _ => Err(Error::FromLuaConversionError{
-                from:lua_value.type_name(),
-                to:"FsBytesOrText",
-                message:Some("hhh".to_string())
-            }),
-        }
+    fn from_lua(lua_value: LuaValue<'lua>, _lua: &'lua Lua) -> LuaResult<Self> {
+        Ok(FsBytesOrText(bytes_of(&lua_value)?))
     }
 }
-impl FsBytesOrText {
-    fn to_bytes(&self) -> Vec<u8> {
-        match self {
-            FsBytesOrText::Bytes(b) => b.clone(),
-            FsBytesOrText::Text(s) => s.as_bytes().to_vec(),
-        }
+
+
+impl LuaFile {
+    /// the open handle, using a closed file is an error rather than a silent no-op
+    fn handle(&mut self) -> LuaResult<&mut FileHandle> {
+        let name = &self.0;
+        self.1
+            .as_mut()
+            .ok_or_else(|| Error::RuntimeError(format!("attempt to use closed file {}", name)))
     }
-}
 
+    fn handle_ref(&self) -> LuaResult<&FileHandle> {
+        self.1
+            .as_ref()
+            .ok_or_else(|| Error::RuntimeError(format!("attempt to use closed file {}", self.0)))
+    }
+
+    /// reads from `offset` without moving the position
+    fn read_at(&mut self, offset: u64, len: Option<u64>) -> LuaResult<Vec<u8>> {
+        let handle = self.handle()?;
+        let mut content = vec![];
+        let stream_pos = handle.stream_position()?;
+        handle.seek(SeekFrom::Start(offset))?;
+        match len {
+            Some(len) => Read::take(&mut *handle, len).read_to_end(&mut content)?,
+            None => handle.read_to_end(&mut content)?,
+        };
+        handle.seek(SeekFrom::Start(stream_pos))?;
+        Ok(content)
+    }
+}
 
 impl UserData for LuaFile {
     fn add_fields<'lua, F: mlua::UserDataFields<'lua, Self>>(fields: &mut F) {
         fields.add_field_method_get("name", |_l, t| Ok(t.0.clone()));
-        // fields.add_field_method_get("size", |l,t|Ok(t.1.stream_len()?.clone()))
+        fields.add_field_method_get("size", |_l, t| Ok(t.handle_ref()?.size()?));
+        fields.add_field_method_get("position", |_l, t| Ok(t.handle_ref()?.position()?));
+        fields.add_field_method_get("closed", |_l, t| Ok(t.1.is_none()));
         fields.add_meta_field_with("__name", |_lua| Ok("LuaFile".to_string()));
     }
     fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
         methods.add_method_mut("write", |_l, t, content: FsBytesOrText| {
-            t.1.write_all(&content.to_bytes())?;
+            t.handle()?.write_all(&content.0)?;
             Ok(())
         });
-        methods.add_method_mut("read", |_l, t, ()| {
-            let mut content = String::new();
-            let stream_pos = t.1.stream_position()?;
-            t.1.seek(std::io::SeekFrom::Start(0))?;
-            t.1.read_to_string(&mut content)?;
-            t.1.seek(std::io::SeekFrom::Start(stream_pos))?;
-            Ok(content)
+        // lua strings hold any bytes, so this works for binary files too
+        methods.add_method_mut("read", |l, t, ()| {
+            let content = t.read_at(0, None)?;
+            l.create_string(&content)
+        });
+        // `len` bytes from `offset` (0 based), everything after it by default
+        methods.add_method_mut("readBytes", |_l, t, (offset, len): (Option<u64>, Option<u64>)| {
+            Ok(Bytes(t.read_at(offset.unwrap_or(0), len)?))
+        });
+        // iterates over the lines from the current position on, like `file:lines()` in lua
+        methods.add_method_mut("lines", |l, t, ()| {
+            let mut content = vec![];
+            t.handle()?.read_to_end(&mut content)?;
+            let mut lines = content
+                .split(|b| *b == b'\n')
+                .map(|line| line.to_vec())
+                .collect::<Vec<_>>();
+            // a trailing newline doesn't start another line
+            if lines.last().map_or(false, |l| l.is_empty()) {
+                lines.pop();
+            }
+            let mut lines = lines.into_iter();
+            l.create_function_mut(move |l, ()| {
+                lines.next().map(|line| l.create_string(&line)).transpose()
+            })
         });
         methods.add_method_mut("clear", |_l, t, ()| {
-            t.1.set_len(0)?;
-            t.1.flush()?;
-            t.1.rewind()?;
+            let handle = t.handle()?;
+            handle.set_len(0)?;
+            handle.flush()?;
+            handle.rewind()?;
+            Ok(())
+        });
+        methods.add_method_mut("truncate", |_l, t, len: u64| {
+            t.handle()?.set_len(len)?;
+            Ok(())
+        });
+        methods.add_method_mut("flush", |_l, t, ()| {
+            t.handle()?.flush()?;
             Ok(())
         });
-        methods.add_method_mut("seek", |_, t, pos: u64| {
-            t.1.seek(std::io::SeekFrom::Start(pos))?;
+        methods.add_method_mut("close", |_l, t, ()| {
+            t.handle()?.flush()?;
+            t.1 = None;
             Ok(())
         });
+        // `whence` is "set" (the default), "cur" or "end", returns the new position
+        methods.add_method_mut("seek", |_, t, (offset, whence): (i64, Option<String>)| {
+            let pos = match whence.as_deref().unwrap_or("set") {
+                "set" => SeekFrom::Start(u64::try_from(offset).map_err(|_| {
+                    Error::RuntimeError("can't seek before the start of the file".to_string())
+                })?),
+                "cur" => SeekFrom::Current(offset),
+                "end" => SeekFrom::End(offset),
+                w => {
+                    return Err(Error::RuntimeError(format!(
+                        "unknown seek origin \"{}\", expected set, cur or end",
+                        w
+                    )))
+                }
+            };
+            Ok(t.handle()?.seek(pos)?)
+        });
         methods.add_method_mut("unzip", |_, t, to:String| {
             let path = Path::new(&to).absolutize()?;
-            is_path_allowed(path.as_ref())?;
+            is_path_allowed(path.as_ref(), FsAccess::Write)?;
             
-            let mut content = vec![];
-            let stream_pos = t.1.stream_position()?;
-            t.1.seek(std::io::SeekFrom::Start(0))?;
-            t.1.read_to_end(&mut content)?;
-            t.1.seek(std::io::SeekFrom::Start(stream_pos))?;
+            let mut content = t.read_at(0, None)?;
 
             let mut read = Cursor::new(&mut content);
             
             let mut z = ZipArchive::new(&mut read).or_else(|e| Err(Error::ExternalError(Arc::new(e))))?;
             
-            z.extract(&path).or_else(|e| Err(Error::ExternalError(Arc::new(e))))?;
+            // extracted entry by entry so every file goes through effects
+            for i in 0..z.len() {
+                let mut entry = z.by_index(i).or_else(|e| Err(Error::ExternalError(Arc::new(e))))?;
+                let out = match entry.enclosed_name() {
+                    Some(name) => path.join(name),
+                    None => continue,
+                };
+                // checked per entry, a symlinked dir in the destination could lead anywhere
+                if entry.is_dir() {
+                    is_path_allowed(out.as_path(), FsAccess::Write)?;
+                    effects::create_dir(&out, true)?;
+                } else {
+                    let mut data = vec![];
+                    entry.read_to_end(&mut data)?;
+                    write_file(&out, &data)?;
+                }
+            }
 
             Ok(())
         })
@@ -131,69 +224,212 @@ impl UserData for LuaFs {
     fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
         methods.add_method("createFile", |_l, t, p: String| {
             let path = Path::new(&p).absolutize()?;
-            is_path_allowed(path.as_ref())?;
-
-            let file = OpenOptions::new()
-                .create(true)
-                .write(true)
-                .read(true)
-                .open(&path)?;
-            let file = LuaFile(path.display().to_string(), file);
-            println!("done");
+            is_path_allowed(path.as_ref(), FsAccess::Write)?;
+
+            let mode = OpenMode {
+                create: true,
+                ..OpenMode::READ_WRITE
+            };
+            let file = effects::open_file(&path, mode)?;
+            let file = LuaFile(path.display().to_string(), Some(file));
             Ok(file)
         });
         methods.add_method("createDir", |_l, t, p: String| {
             let path = Path::new(&p).absolutize()?;
-            is_path_allowed(path.as_ref())?;
-            create_dir(&path)?;
+            is_path_allowed(path.as_ref(), FsAccess::Write)?;
+            effects::create_dir(&path, false)?;
             // let file = LuaFile(path.display().to_string(), file);
 
             Ok(path.as_ref().display().to_string())
         });
         methods.add_method("openDir", |_l, t, p: String| {
             let path = Path::new(&p).absolutize()?;
-            is_path_allowed(path.as_ref())?;
+            is_path_allowed(path.as_ref(), FsAccess::Read)?;
 
-            let dir = read_dir(path)?;
-            // let file = LuaFile(path.display().to_string(), file);
-
-            Ok(dir
-                .map(|f| f.unwrap().path().display().to_string())
-                .collect::<Vec<_>>())
+            read_dir(path)?
+                .map(|f| Ok(f?.path().display().to_string()))
+                .collect::<mlua::Result<Vec<_>>>()
         });
-        methods.add_method("openFile", |_l, t, p: String| {
+        // every entry under `dir` as {path, relative, type, size},
+        // `options` is {include, exclude, max_depth, gitignore}
+        methods.add_method("walk", |l, _t, (p, options): (String, Option<LuaValue>)| {
             let path = Path::new(&p).absolutize()?;
-            is_path_allowed(path.as_ref())?;
-
-            let file = OpenOptions::new()
-                .create(false)
-                .write(true)
-                .read(true)
-                .open(&path)?;
-            let file = LuaFile(path.display().to_string(), file);
+            let options: WalkOptions = match options {
+                None | Some(LuaValue::Nil) => WalkOptions::default(),
+                Some(v) => l.from_value(v)?,
+            };
+            l.to_value(&walk(path.as_ref(), &options)?)
+        });
+        // the entries matching a pattern like `src/**/*.rs`, relative ones are from the project dir
+        methods.add_method("glob", |l, t, pattern: String| {
+            let pattern = if Path::new(&pattern).is_absolute() {
+                pattern
+            } else {
+                Path::new(&t.0).join(&pattern).display().to_string()
+            };
+            l.to_value(&glob(&pattern)?)
+        });
+        // `mode` is one of lua's r, r+, w, a or x for a new file, defaults to r+
+        methods.add_method("openFile", |_l, t, (p, mode): (String, Option<String>)| {
+            let path = Path::new(&p).absolutize()?;
+            let mode_str = mode.unwrap_or_else(|| "r+".to_string());
+            let mode = OpenMode::parse(&mode_str).ok_or_else(|| {
+                Error::RuntimeError(format!("unknown open mode \"{}\"", mode_str))
+            })?;
+            let access = if mode.write { FsAccess::Write } else { FsAccess::Read };
+            is_path_allowed(path.as_ref(), access)?;
+
+            let file = effects::open_file(&path, mode)?;
+            let file = LuaFile(path.display().to_string(), Some(file));
             Ok(file)
         });
         methods.add_method("exists", |_l, t, p: String| {
             let path = Path::new(&p).absolutize()?;
-            is_path_allowed(path.as_ref())?;
+            is_path_allowed(path.as_ref(), FsAccess::Read)?;
 
-            Ok(path.exists())
+            Ok(effects::exists(&path))
         });
         methods.add_method("copy", |_l, t, (fp, tp): (String, String)| {
             let path = Path::new(&tp).absolutize()?;
-            is_path_allowed(path.as_ref())?;
+            is_path_allowed(path.as_ref(), FsAccess::Write)?;
+            let pathf = Path::new(&fp).absolutize()?;
+            is_path_allowed(pathf.as_ref(), FsAccess::Read)?;
 
-            crate::utils::copy(fp, path)?;
+            // every entry is checked, a symlink in either tree could point anywhere
+            crate::utils::copy_with(
+                pathf,
+                path,
+                |dir| -> LuaResult<()> {
+                    is_path_allowed(dir, FsAccess::Write)?;
+                    Ok(effects::create_dir(dir, true)?)
+                },
+                |from, to| {
+                    is_path_allowed(from, FsAccess::Read)?;
+                    write_file(to, &effects::read(from)?)
+                },
+            )?;
             Ok(())
         });
+        // copies a template tree, both the source and destination have to be allowed
+        methods.add_method("copyTemplate", |l, _t, (fp, tp, vars, opts): (String, String, LuaValue, Option<LuaTable>)| {
+            let path = Path::new(&tp).absolutize()?;
+            is_path_allowed(path.as_ref(), FsAccess::Write)?;
+            let pathf = Path::new(&fp).absolutize()?;
+            is_path_allowed(pathf.as_ref(), FsAccess::Read)?;
+
+            let vars = l.from_value::<serde_json::Value>(vars)?;
+            let (ignore, copy_binary) = match opts {
+                Some(opts) => (
+                    opts.get::<_, Option<Vec<String>>>("ignore")?.unwrap_or_default(),
+                    opts.get::<_, Option<bool>>("copy_binary")?.unwrap_or(false),
+                ),
+                None => (vec![], false),
+            };
+            // binary files can't be rendered, they're left out unless asked for
+            let binary = if copy_binary { BinaryFiles::Copy } else { BinaryFiles::Skip };
+            copy_template(&pathf, &path, &vars, &ignore, binary)
+        });
         methods.add_method("move", |_l, t, (fp, tp): (String, String)| {
             let path = Path::new(&tp).absolutize()?;
-            is_path_allowed(path.as_ref())?;
+            is_path_allowed(path.as_ref(), FsAccess::Write)?;
             let pathf = Path::new(&fp).absolutize()?;
-            is_path_allowed(path.as_ref())?;
+            is_path_allowed(pathf.as_ref(), FsAccess::Write)?;
+
+            effects::rename(&pathf, &path)?;
+            Ok(())
+        });
+        // a file or symlink, dirs need removeDir
+        methods.add_method("remove", |_l, _t, p: String| {
+            let path = Path::new(&p).absolutize()?;
+            // a link is removed, not what it points to
+            is_entry_allowed(path.as_ref(), FsAccess::Write)?;
+
+            if effects::stat(&path)?.map_or(false, |s| s.kind == "dir") {
+                return Err(Error::RuntimeError(format!(
+                    "{} is a dir, use removeDir",
+                    path.display()
+                )));
+            }
+            effects::remove(&path, false)?;
+            Ok(())
+        });
+        // `options` is {recursive}, without it the dir has to be empty
+        methods.add_method("removeDir", |_l, _t, (p, options): (String, Option<LuaTable>)| {
+            let path = Path::new(&p).absolutize()?;
+            is_path_allowed(path.as_ref(), FsAccess::Write)?;
+            let recursive = match options {
+                Some(options) => options.get::<_, Option<bool>>("recursive")?.unwrap_or(false),
+                None => false,
+            };
 
-            fs::rename(pathf, path)?;
+            if !effects::stat(&path)?.map_or(true, |s| s.kind == "dir") {
+                return Err(Error::RuntimeError(format!("{} isn't a dir", path.display())));
+            }
+            effects::remove(&path, recursive)?;
+            Ok(())
+        });
+        // {type, size, modified, mode, readonly}, nil if nothing is there
+        methods.add_method("stat", |l, _t, p: String| {
+            let path = Path::new(&p).absolutize()?;
+            is_path_allowed(path.as_ref(), FsAccess::Read)?;
+
+            match effects::stat(&path)? {
+                Some(stat) => l.to_value(&stat),
+                None => Ok(LuaValue::Nil),
+            }
+        });
+        // `mode` is an octal string like "755", or the number it stands for
+        methods.add_method("chmod", |_l, _t, (p, mode): (String, LuaValue)| {
+            let path = Path::new(&p).absolutize()?;
+            is_path_allowed(path.as_ref(), FsAccess::Write)?;
+
+            // numbers too are read as octal digits, 755 is rwxr-xr-x
+            let mode = match &mode {
+                LuaValue::Integer(i) => parse_mode(&i.to_string()),
+                LuaValue::String(s) => s.to_str().ok().and_then(parse_mode),
+                _ => None,
+            }
+            .ok_or_else(|| Error::RuntimeError("chmod needs a mode like \"755\"".to_string()))?;
+            effects::chmod(&path, mode)?;
+            Ok(())
+        });
+        // a relative target is relative to the link's dir, like `ln -s`
+        methods.add_method("symlink", |_l, _t, (target, p): (String, String)| {
+            let path = Path::new(&p).absolutize()?;
+            is_entry_allowed(path.as_ref(), FsAccess::Write)?;
+            // the link gives access to its target, so that has to be readable too
+            let target_path = match path.parent() {
+                Some(dir) if Path::new(&target).is_relative() => dir.join(&target),
+                _ => PathBuf::from(&target),
+            };
+            is_path_allowed(target_path, FsAccess::Read)?;
+
+            effects::symlink(Path::new(&target), &path)?;
+            Ok(())
+        });
+        methods.add_method("touch", |_l, _t, p: String| {
+            let path = Path::new(&p).absolutize()?;
+            is_path_allowed(path.as_ref(), FsAccess::Write)?;
+
+            effects::touch(&path)?;
             Ok(())
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modes_are_octal() {
+        assert_eq!(parse_mode("755"), Some(0o755));
+        assert_eq!(parse_mode("0o644"), Some(0o644));
+        assert_eq!(parse_mode("4755"), Some(0o4755));
+        assert_eq!(parse_mode(&755.to_string()), Some(0o755));
+        assert_eq!(parse_mode("789"), None);
+        assert_eq!(parse_mode("17777"), None);
+        assert_eq!(parse_mode("-1"), None);
+        assert_eq!(parse_mode("rwx"), None);
+    }
+}
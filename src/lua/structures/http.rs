@@ -4,18 +4,65 @@ use std::{
     sync::{Arc, Mutex}, time::Duration,
 };
 
-use mlua::{Error, LuaSerdeExt, UserData, Value};
+use mlua::{Error, Lua, LuaSerdeExt, UserData, Value};
 use reqwest::{
     header::{HeaderMap, HeaderName},
     Method,
 };
 use serde::{Deserialize, Serialize};
 
-use super::permissions::{PERMISSIONS_MANAGER, Permission};
+use super::{
+    bytes::Bytes,
+    effects::{self, Effect},
+    permissions::{PERMISSIONS_MANAGER, Permission},
+};
 
 #[derive(Clone)]
 pub struct LuaHttp(pub Arc<Mutex<reqwest::Client>>);
 
+/// redirects followed by a single request, like reqwest's default
+const MAX_REDIRECTS: usize = 10;
+
+impl LuaHttp {
+    /// redirects are followed by `request` itself, so every hop goes through the permission check
+    pub fn new() -> reqwest::Result<LuaHttp> {
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()?;
+        Ok(LuaHttp(Arc::new(Mutex::new(client))))
+    }
+}
+
+/// the `Http` permission needed to reach `url`
+fn http_permission(u: &url::Url) -> mlua::Result<Permission> {
+    let domain = u.host_str().ok_or(Error::RuntimeError("invalid url".to_string()))?;
+    if !["http","https"].contains(&u.scheme()) {
+        return Err(Error::RuntimeError("invalid url".to_string()));
+    }
+    Ok(Permission::Http(format!(
+        "{}://{}:{}{}",
+        u.scheme(),
+        domain,
+        u.port_or_known_default().unwrap_or_default(),
+        u.path()
+    )))
+}
+
+/// where a redirect response points to, `None` if it isn't one
+fn redirect_target(current: &url::Url, status: reqwest::StatusCode, headers: &HeaderMap) -> mlua::Result<Option<url::Url>> {
+    if !status.is_redirection() {
+        return Ok(None);
+    }
+    let Some(location) = headers.get(reqwest::header::LOCATION) else {
+        return Ok(None);
+    };
+    let location = location.to_str().map_err(|e| Error::ExternalError(Arc::new(e)))?;
+    current
+        .join(location)
+        .map(Some)
+        .map_err(|e| Error::RuntimeError(format!("invalid redirect location {} : {}", location, e)))
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub enum ContentTypes {
     Text,
@@ -45,28 +92,55 @@ struct LuaHttpResponse {
     status: u16,
     body: ContentTypesResponse,
     headers: Vec<(String, String)>,
+    // nothing was sent, status and body are placeholders
+    dry_run: bool,
 }
 
 impl UserData for LuaHttpResponse {}
 
+impl LuaHttpResponse {
+    /// serde can't make userdata, so a byte body is put in as `Bytes` afterwards
+    fn to_lua(mut self, l: &Lua) -> mlua::Result<Value> {
+        let body = std::mem::replace(&mut self.body, ContentTypesResponse::None);
+        let value = l.to_value(&self)?;
+        if let Value::Table(t) = &value {
+            let content = l.create_table()?;
+            match body {
+                ContentTypesResponse::Text(s) => content.set("Text", s)?,
+                ContentTypesResponse::Bytes(b) => content.set("Bytes", Bytes(b))?,
+                ContentTypesResponse::None => {}
+            }
+            t.set("body", content)?;
+        }
+        Ok(value)
+    }
+}
+
 impl UserData for LuaHttp {
     fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
         methods.add_async_method("request", |l, t, options: Value| async move {
             let options = l.from_value::<LuaHttpRequest>(options)?;
 
-            if let Ok(u) = url::Url::parse(&options.url) {
-                let domain = u.host_str().ok_or(Error::RuntimeError("invalid url".to_string()))?;
-                if !vec!["http","https"].contains(&u.scheme()) {
-                    return Err(Error::RuntimeError("invalid url".to_string()));
-                }
-                let p = Permission::Http(domain.to_string());
-                PERMISSIONS_MANAGER.lock().unwrap().ask_for_access(&p)?;
-            } else {
+            let Ok(mut url) = url::Url::parse(&options.url) else {
                 return Err(Error::RuntimeError("Invalid URL".to_string()));
-            }
+            };
+            PERMISSIONS_MANAGER.lock().unwrap().ask_for_access(&http_permission(&url)?)?;
 
+            if effects::is_dry_run() {
+                effects::record(Effect::Http(options.method.to_uppercase(), options.url.clone()));
+                return LuaHttpResponse {
+                    body: match options.content_type {
+                        Some(ContentTypes::Bytes) => ContentTypesResponse::Bytes(vec![]),
+                        Some(ContentTypes::Text) | None => ContentTypesResponse::Text(String::new()),
+                    },
+                    headers: vec![],
+                    status: 0,
+                    dry_run: true,
+                }
+                .to_lua(l);
+            }
 
-            let client = t.0.lock().unwrap();
+            let client = t.0.lock().unwrap().clone();
 
             let mut header_map: HeaderMap = HeaderMap::default();
             for (k, v) in options.headers {
@@ -74,25 +148,50 @@ impl UserData for LuaHttp {
                 header_map.insert(hn, v.parse().unwrap());
             }
 
-            let result = client
-                .request(
-                    match options.method.to_uppercase().as_str() {
-                        "GET" => Method::GET,
-                        "POST" => Method::POST,
-                        "PATCH" => Method::PATCH,
-                        "OPTIONS" => Method::OPTIONS,
-                        "PUT" => Method::PUT,
-                        "HEAD" => Method::HEAD,
-                        _ => Err(Error::RuntimeError("Invalid METHOD".to_string()))?,
-                    },
-                    options.url,
-                )
-                .headers(header_map)
-                .body(options.body.unwrap_or_default())
-                .timeout(Duration::from_secs(120)) // 2 mins
-                .send()
-                .await
-                .or_else(|e| Err(Error::ExternalError(Arc::new(e))))?;
+            let mut method = match options.method.to_uppercase().as_str() {
+                "GET" => Method::GET,
+                "POST" => Method::POST,
+                "PATCH" => Method::PATCH,
+                "OPTIONS" => Method::OPTIONS,
+                "PUT" => Method::PUT,
+                "HEAD" => Method::HEAD,
+                _ => Err(Error::RuntimeError("Invalid METHOD".to_string()))?,
+            };
+            let mut body = options.body.unwrap_or_default();
+
+            let mut hops = 0;
+            let result = loop {
+                let result = client
+                    .request(method.clone(), url.clone())
+                    .headers(header_map.clone())
+                    .body(body.clone())
+                    .timeout(Duration::from_secs(120)) // 2 mins
+                    .send()
+                    .await
+                    .or_else(|e| Err(Error::ExternalError(Arc::new(e))))?;
+
+                let Some(next) = redirect_target(&url, result.status(), result.headers())? else {
+                    break result;
+                };
+                hops += 1;
+                if hops > MAX_REDIRECTS {
+                    return Err(Error::RuntimeError(format!("too many redirects from {}", options.url)));
+                }
+                // every hop needs its own permission, an allowed host could send us anywhere
+                PERMISSIONS_MANAGER.lock().unwrap().ask_for_access(&http_permission(&next)?)?;
+                if next.host_str() != url.host_str() {
+                    for h in [reqwest::header::AUTHORIZATION, reqwest::header::COOKIE, reqwest::header::PROXY_AUTHORIZATION] {
+                        header_map.remove(h);
+                    }
+                }
+                // same as browsers, only 307 and 308 keep the method and body
+                let status = result.status().as_u16();
+                if status != 307 && status != 308 && method != Method::HEAD {
+                    method = Method::GET;
+                    body = String::new();
+                }
+                url = next;
+            };
 
             // println!("made req");
 
@@ -115,13 +214,48 @@ impl UserData for LuaHttp {
                 }
             }
 
-            Ok(l.to_value(&LuaHttpResponse {
+            LuaHttpResponse {
                 body: resp_content,
                 headers,
                 status,
-            }))
+                dry_run: false,
+            }
+            .to_lua(l)
         })
     }
 
     fn add_fields<'lua, F: mlua::UserDataFields<'lua, Self>>(_fields: &mut F) {}
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permissions_carry_the_port() {
+        let u = url::Url::parse("https://api.example.com/v1/items?page=2").unwrap();
+        assert_eq!(
+            http_permission(&u).unwrap(),
+            Permission::Http("https://api.example.com:443/v1/items".to_string())
+        );
+        assert!(http_permission(&url::Url::parse("ftp://example.com/").unwrap()).is_err());
+    }
+
+    #[test]
+    fn redirects_are_resolved_against_the_current_url() {
+        let current = url::Url::parse("https://api.example.com/v1/items").unwrap();
+        let mut headers = HeaderMap::default();
+        headers.insert(reqwest::header::LOCATION, "/v2/items".parse().unwrap());
+        assert_eq!(
+            redirect_target(&current, reqwest::StatusCode::FOUND, &headers).unwrap(),
+            Some(url::Url::parse("https://api.example.com/v2/items").unwrap())
+        );
+
+        headers.insert(reqwest::header::LOCATION, "https://evil.example.org/".parse().unwrap());
+        let next = redirect_target(&current, reqwest::StatusCode::TEMPORARY_REDIRECT, &headers).unwrap().unwrap();
+        assert_eq!(next.host_str(), Some("evil.example.org"));
+
+        assert_eq!(redirect_target(&current, reqwest::StatusCode::OK, &headers).unwrap(), None);
+        assert_eq!(redirect_target(&current, reqwest::StatusCode::FOUND, &HeaderMap::default()).unwrap(), None);
+    }
+}
@@ -1,4 +1,12 @@
+pub mod bytes;
+pub mod conflicts;
+pub mod effects;
 pub mod fs;
 pub mod http;
+pub mod params;
 pub mod scripts;
+pub mod template;
 pub mod permissions;
+pub mod process;
+pub mod prompt;
+pub mod walk;
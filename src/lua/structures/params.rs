@@ -0,0 +1,242 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ParamType {
+    #[default]
+    String,
+    Number,
+    Integer,
+    Boolean,
+}
+
+/// a parameter declared by a script through `s.params`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ScriptParam {
+    pub name: String,
+    #[serde(rename = "type", default)]
+    pub kind: ParamType,
+    #[serde(default)]
+    pub default: Option<JsonValue>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub choices: Option<Vec<JsonValue>>,
+    #[serde(default)]
+    pub required: bool,
+}
+
+impl fmt::Display for ScriptParam {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} : {:?}", self.name, self.kind)?;
+        if self.required {
+            write!(f, " (required)")?;
+        }
+        if let Some(default) = &self.default {
+            write!(f, " = {}", default)?;
+        }
+        if let Some(choices) = &self.choices {
+            let choices = choices.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+            write!(f, " [{}]", choices.join(", "))?;
+        }
+        if let Some(description) = &self.description {
+            write!(f, " - {}", description)?;
+        }
+        Ok(())
+    }
+}
+
+/// numbers are compared by value, lua gives `80` as an integer but a number param parses to 80.0
+fn same_value(a: &JsonValue, b: &JsonValue) -> bool {
+    match (a, b) {
+        (JsonValue::Number(a), JsonValue::Number(b)) => a.as_f64() == b.as_f64(),
+        _ => a == b,
+    }
+}
+
+impl ScriptParam {
+    fn is_choice(&self, value: &JsonValue) -> bool {
+        self.choices
+            .as_ref()
+            .map_or(true, |choices| choices.iter().any(|c| same_value(c, value)))
+    }
+
+    /// how the param is shown in `proj list`, e.g. `port:integer=80`
+    pub fn summary(&self) -> String {
+        let kind = format!("{:?}", self.kind).to_lowercase();
+        let mut summary = format!("{}:{}", self.name, kind);
+        if let Some(default) = &self.default {
+            summary.push_str(&format!("={}", default));
+        } else if self.required {
+            summary.push('!');
+        }
+        summary
+    }
+
+    /// a default which isn't one of the choices could never be given on the command line
+    pub fn check(&self) -> Result<(), String> {
+        if let (Some(default), Some(choices)) = (&self.default, &self.choices)
+            && !self.is_choice(default)
+        {
+            let choices = choices.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+            return Err(format!(
+                "the default of param \"{}\" ({}) isn't one of {}",
+                self.name,
+                default,
+                choices.join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    fn parse(&self, raw: &str) -> Result<JsonValue, String> {
+        let value = match self.kind {
+            ParamType::String => JsonValue::String(raw.to_string()),
+            ParamType::Number => raw
+                .parse::<f64>()
+                .ok()
+                .and_then(serde_json::Number::from_f64)
+                .map(JsonValue::Number)
+                .ok_or_else(|| format!("argument \"{}\" should be a number", self.name))?,
+            ParamType::Integer => raw
+                .parse::<i64>()
+                .map(|i| JsonValue::Number(i.into()))
+                .map_err(|_| format!("argument \"{}\" should be an integer", self.name))?,
+            ParamType::Boolean => match raw.to_lowercase().as_str() {
+                "true" | "yes" | "y" | "1" => JsonValue::Bool(true),
+                "false" | "no" | "n" | "0" => JsonValue::Bool(false),
+                _ => return Err(format!("argument \"{}\" should be a boolean", self.name)),
+            },
+        };
+        if let Some(choices) = &self.choices && !self.is_choice(&value) {
+            let choices = choices.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+            return Err(format!(
+                "argument \"{}\" should be one of {}",
+                self.name,
+                choices.join(", ")
+            ));
+        }
+        Ok(value)
+    }
+}
+
+/// splits `key=value` arguments given on the command line
+pub fn parse_raw_args(args: &[String]) -> Result<Vec<(String, String)>, String> {
+    args.iter()
+        .map(|arg| {
+            arg.split_once('=')
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .ok_or_else(|| format!("argument \"{}\" should be written as key=value", arg))
+        })
+        .collect()
+}
+
+/// the argument names which aren't declared params, they have to be answers for `prompt`
+pub fn undeclared_args(params: &[ScriptParam], raw: &[(String, String)]) -> Vec<String> {
+    let mut names = raw
+        .iter()
+        .map(|(k, _)| k.clone())
+        .filter(|k| !params.iter().any(|p| &p.name == k))
+        .collect::<Vec<_>>();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// checks the given arguments against the declared params, filling in defaults.
+/// undeclared arguments are left alone, they may be answers for `prompt`,
+/// see `undeclared_args`.
+pub fn validate_args(
+    params: &[ScriptParam],
+    raw: &[(String, String)],
+) -> Result<Map<String, JsonValue>, String> {
+    let mut args = Map::new();
+    for param in params {
+        let value = match raw.iter().rev().find(|(k, _)| k == &param.name) {
+            Some((_, v)) => Some(param.parse(v)?),
+            None => param.default.clone(),
+        };
+        match value {
+            Some(value) => {
+                args.insert(param.name.clone(), value);
+            }
+            None if param.required => {
+                return Err(format!("missing required argument \"{}\"", param.name))
+            }
+            None => {}
+        }
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn param(value: JsonValue) -> ScriptParam {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn raw(args: &[&str]) -> Vec<(String, String)> {
+        parse_raw_args(&args.iter().map(|a| a.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    #[test]
+    fn raw_args_need_a_value() {
+        assert_eq!(raw(&["a=1", "b=x=y", "c="]), vec![
+            ("a".to_string(), "1".to_string()),
+            ("b".to_string(), "x=y".to_string()),
+            ("c".to_string(), String::new()),
+        ]);
+        assert!(parse_raw_args(&["a".to_string()]).is_err());
+    }
+
+    #[test]
+    fn args_are_typed() {
+        let params = vec![
+            param(json!({"name": "name", "required": true})),
+            param(json!({"name": "port", "type": "integer", "default": 8080})),
+            param(json!({"name": "ratio", "type": "number"})),
+            param(json!({"name": "force", "type": "boolean"})),
+        ];
+        let args = validate_args(&params, &raw(&["name=app", "ratio=0.5", "force=yes", "name=other"])).unwrap();
+        assert_eq!(JsonValue::Object(args), json!({"name": "other", "port": 8080, "ratio": 0.5, "force": true}));
+
+        assert!(validate_args(&params, &raw(&["port=80"])).is_err());
+        assert!(validate_args(&params, &raw(&["name=a", "port=eighty"])).is_err());
+        assert!(validate_args(&params, &raw(&["name=a", "force=maybe"])).is_err());
+        assert!(validate_args(&params, &raw(&["name=a", "ratio=x"])).is_err());
+    }
+
+    #[test]
+    fn choices() {
+        // lua gives integers for whole numbers, number params parse to floats
+        let port = param(json!({"name": "port", "type": "number", "choices": [80, 443], "default": 80}));
+        assert!(port.check().is_ok());
+        assert_eq!(validate_args(std::slice::from_ref(&port), &raw(&["port=80"])).unwrap()["port"], json!(80.0));
+        assert!(validate_args(&[port], &raw(&["port=81"])).is_err());
+
+        let bad_default = param(json!({"name": "lang", "choices": ["rust", "go"], "default": "c"}));
+        assert!(bad_default.check().is_err());
+    }
+
+    #[test]
+    fn undeclared_args_are_listed_once() {
+        let params = vec![param(json!({"name": "name"}))];
+        assert_eq!(
+            undeclared_args(&params, &raw(&["b=1", "name=x", "a=1", "b=2"])),
+            vec!["a".to_string(), "b".to_string()]
+        );
+    }
+
+    #[test]
+    fn summaries() {
+        assert_eq!(param(json!({"name": "port", "type": "integer", "default": 80})).summary(), "port:integer=80");
+        assert_eq!(param(json!({"name": "Name", "required": true})).summary(), "Name:string!");
+    }
+}
@@ -0,0 +1,398 @@
+use core::fmt;
+use std::{sync::{Arc, Mutex}, fmt::Display};
+use std::error::Error;
+use mlua::{UserData, LuaSerdeExt,ExternalError};
+use serde::{Serialize, Deserialize};
+use mlua::prelude::*;
+
+use crate::{lua::builtin::PREFIX, utils::resolve_path};
+
+use self::{policy::{PermissionPolicy, PolicyFile}, prompt::{PromptAnswer, Prompter}, rules::{split_words, HttpRule}, store::StoredPermissions};
+
+pub mod policy;
+pub mod prompt;
+pub mod rules;
+pub mod store;
+
+/// writing implies being able to read
+#[derive(Serialize,Deserialize,Debug,Clone,Copy,PartialEq, Eq, PartialOrd, Ord)]
+pub enum FsAccess {
+    Read,
+    Write,
+}
+
+#[derive(Serialize,Deserialize,Debug,Clone,PartialEq, Eq, PartialOrd, Ord)]
+#[serde(from = "PermissionRepr")]
+pub enum Permission {
+    Fs(FsAccess,String),
+    Http(String),
+    Script(String),
+    // the arguments of a command, as written by `join_words`
+    Command(String),
+    // a whole `sh -c` / `cmd /C` line, only ever granted exactly
+    Shell(String),
+}
+
+/// what permissions are read from, stores and policy files written before fs rules had an
+/// access level still have `Fs = "/path"`, which meant full access
+#[derive(Deserialize)]
+enum PermissionRepr {
+    Fs(FsRepr),
+    Http(String),
+    Script(String),
+    Command(String),
+    Shell(String),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FsRepr {
+    Scoped(FsAccess,String),
+    Legacy(String),
+}
+
+impl From<PermissionRepr> for Permission {
+    fn from(p: PermissionRepr) -> Self {
+        match p {
+            PermissionRepr::Fs(FsRepr::Scoped(a,s)) => Permission::Fs(a,s),
+            PermissionRepr::Fs(FsRepr::Legacy(s)) => Permission::Fs(FsAccess::Write,s),
+            PermissionRepr::Http(s) => Permission::Http(s),
+            PermissionRepr::Script(s) => Permission::Script(s),
+            PermissionRepr::Command(s) => Permission::Command(s),
+            PermissionRepr::Shell(s) => Permission::Shell(s),
+        }
+    }
+}
+
+impl Display for Permission {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Permission::Fs(FsAccess::Read,s) => write!(f, "read file system @ {}", s),
+            Permission::Fs(FsAccess::Write,s) => write!(f, "write file system @ {}", s),
+            Permission::Http(s) => write!(f, "http access to \"{}\"", s),
+            Permission::Script(s) => write!(f, "script \"{}\"", s),
+            Permission::Command(s) => write!(f, "command \"{}\"", s),
+            Permission::Shell(s) => write!(f, "shell command \"{}\"", s),
+        }
+    }
+}
+
+impl Permission {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Permission::Fs(..) => "Fs",
+            Permission::Http(_) => "Http",
+            Permission::Script(_) => "Script",
+            Permission::Command(_) => "Command",
+            Permission::Shell(_) => "Shell",
+        }
+    }
+    pub fn value(&self) -> &str {
+        match self {
+            Permission::Fs(_,s) | Permission::Http(s) | Permission::Script(s) | Permission::Command(s) | Permission::Shell(s) => s,
+        }
+    }
+    /// the same kind of permission for a different value
+    pub fn with_value(&self,v:String) -> Permission {
+        match self {
+            Permission::Fs(a,_) => Permission::Fs(*a,v),
+            Permission::Http(_) => Permission::Http(v),
+            Permission::Script(_) => Permission::Script(v),
+            Permission::Command(_) => Permission::Command(v),
+            Permission::Shell(_) => Permission::Shell(v),
+        }
+    }
+    /// the rule granted when a request is allowed always, http requests are widened to their origin
+    pub fn default_rule(&self) -> Permission {
+        match self {
+            Permission::Http(s) => match HttpRule::parse(s) {
+                Some(rule) => Permission::Http(rule.origin()),
+                None => self.clone(),
+            },
+            _ => self.clone(),
+        }
+    }
+    /// whether the rule `self` applies to the request `p`.
+    /// a read rule only allows reads, but denying reads also denies writes.
+    fn covers(&self,p:&Permission,denial:bool) -> bool {
+        match (self,p) {
+            (Permission::Fs(xa,x), Permission::Fs(pa,p)) => {
+                let access = if denial { xa <= pa } else { xa >= pa };
+                access && resolve_path(p).starts_with(resolve_path(x))
+            }
+            (Permission::Http(x), Permission::Http(p)) => match (HttpRule::parse(x),HttpRule::parse(p)) {
+                (Some(x),Some(p)) => x.matches(&p),
+                _ => false,
+            },
+            // compared argument by argument, so "git" doesn't allow "gitk"
+            (Permission::Command(x), Permission::Command(p)) => {
+                split_words(p).starts_with(&split_words(x))
+            }
+            // anything after the rule could be more shell, so only the exact line
+            (Permission::Shell(x), Permission::Shell(p)) => x.trim() == p.trim(),
+            // the whole name, "build" doesn't allow "build-release".
+            // `run` resolves "x" to "builtin:x", a rule for "x" still covers it.
+            (Permission::Script(x), Permission::Script(p)) => {
+                p == x || p.strip_prefix(PREFIX) == Some(x.as_str())
+            }
+            _=>false
+        }
+    }
+}
+
+impl UserData for Permission {}
+
+#[derive(Serialize,Deserialize,Debug,Clone,Default)]
+
+pub struct Permissions{
+    pub allowed: Vec<Permission>,
+    pub denied: Vec<Permission>,
+    // granted by proj itself (e.g. the project dir), these are never remembered
+    #[serde(skip)]
+    pub implicit: Vec<Permission>,
+    #[serde(skip)]
+    pub policy: PermissionPolicy,
+    #[serde(skip)]
+    pub prompt: Prompter,
+    // when the script declared what it needs, nothing else is ever asked for
+    #[serde(skip)]
+    pub manifest: Option<Vec<Permission>>,
+}
+
+#[derive(Debug)]
+pub struct PermissionError(pub Permission);
+
+impl fmt::Display for PermissionError {
+	fn fmt<'a>(&self, f: &'a mut fmt::Formatter) -> fmt::Result {
+		f.write_str(&format!("Permission Error ({} refused : {})",self.0.kind(),self.0))
+	}
+}
+
+impl Error for PermissionError {
+
+}
+
+impl Permissions {
+    pub fn grant(&mut self,p:Permission) {
+        self.implicit.push(p.clone());
+        self.allowed.push(p);
+    }
+    pub fn apply_policy(&mut self,policy:&PolicyFile) {
+        self.implicit.extend(policy.allowed.iter().cloned());
+        self.implicit.extend(policy.denied.iter().cloned());
+        self.allowed.extend(policy.allowed.iter().cloned());
+        self.denied.extend(policy.denied.iter().cloned());
+    }
+    pub fn restore(&mut self,stored:&StoredPermissions) {
+        self.allowed.extend(stored.allowed.iter().cloned());
+        self.denied.extend(stored.denied.iter().cloned());
+    }
+    /// the decisions which should be remembered for the next run
+    pub fn persistent(&self) -> (Vec<Permission>,Vec<Permission>) {
+        (
+            self.allowed.iter().filter(|p| !self.implicit.contains(p)).cloned().collect(),
+            self.denied.iter().filter(|p| !self.implicit.contains(p)).cloned().collect(),
+        )
+    }
+    /// asks for everything a script declared in one go, before it runs
+    pub fn ask_for_manifest(&mut self,manifest:Vec<Permission>) -> LuaResult<()> {
+        let missing = manifest.iter().filter(|p| !self.is_allowed(p)).cloned().collect::<Vec<_>>();
+        if let Some(p) = missing.iter().find(|p| self.is_denied(p)) {
+            return Err(mlua::Error::ExternalError(Arc::new(PermissionError(p.clone()))))
+        }
+        if let Some(first) = missing.first() {
+            match self.policy {
+                PermissionPolicy::Allow => missing.iter().for_each(|p| self.grant(p.clone())),
+                PermissionPolicy::Deny | PermissionPolicy::PolicyFile => {
+                    return Err(mlua::Error::ExternalError(Arc::new(PermissionError(first.clone()))))
+                }
+                PermissionPolicy::Interactive => {
+                    if !self.prompt.0.ask_all(&missing) {
+                        return Err(mlua::Error::ExternalError(Arc::new(PermissionError(first.clone()))))
+                    }
+                    self.allowed.extend(missing.iter().cloned());
+                }
+            }
+        }
+        self.manifest = Some(manifest);
+        Ok(())
+    }
+    pub fn ask_for_access<'h>(&mut self,p:&'h Permission) -> LuaResult<()> {
+
+        if self.is_allowed(&p) {
+            return Ok(())
+        }
+        else if self.is_denied(&p) {
+            return Err(mlua::Error::ExternalError(Arc::new(PermissionError(p.clone()))))
+        }
+
+        if self.manifest.is_some() {
+            return Err(mlua::Error::ExternalError(Arc::new(PermissionError(p.clone()))))
+        }
+
+        match self.policy {
+            PermissionPolicy::Interactive => {}
+            PermissionPolicy::Allow => {
+                // auto granted, so not something to remember
+                self.grant(p.clone());
+                return Ok(())
+            }
+            PermissionPolicy::Deny | PermissionPolicy::PolicyFile => {
+                return Err(mlua::Error::ExternalError(Arc::new(PermissionError(p.clone()))))
+            }
+        }
+
+        match self.prompt.0.ask(p) {
+            PromptAnswer::AllowOnce => Ok(()),
+            PromptAnswer::AllowAlways(rule) => {
+                self.allowed.push(rule);
+                Ok(())
+            }
+            PromptAnswer::DenyOnce => Err(mlua::Error::ExternalError(Arc::new(PermissionError(p.clone())))),
+            PromptAnswer::DenyAlways(rule) => {
+                self.denied.push(rule);
+                Err(mlua::Error::ExternalError(Arc::new(PermissionError(p.clone()))))
+            }
+        }
+    }
+    #[inline]
+    pub fn is_allowed(&self,p:&Permission) -> bool {
+        self.allowed.iter().any( |x:&Permission| x.covers(p,false))
+    }
+    #[inline]
+    pub fn is_denied(&self,p:&Permission) -> bool {
+        self.denied.iter().any( |x:&Permission| x.covers(p,true))
+    }
+}
+
+impl UserData for Permissions {
+
+    fn add_fields<'lua, F: mlua::UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("allowed", |l,t| {
+            l.to_value(&t.allowed)
+        });
+        fields.add_field_method_get("denied", |l,t| {
+            l.to_value(&t.denied)
+        });
+        
+        fields.add_meta_field_with("__name", |_lua| Ok("Permissions".to_string()));
+    }
+
+}
+
+lazy_static::lazy_static! {
+    pub static ref PERMISSIONS_MANAGER : Arc<Mutex<Permissions>> = Arc::new(Mutex::new(Permissions { ..Default::default() }));
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    fn fs(access: FsAccess, p: &str) -> Permission {
+        Permission::Fs(access, p.to_string())
+    }
+
+    /// allows writing (and reading) under `dir` until dropped,
+    /// the policy is shared by every test so it's never changed
+    pub(crate) struct Granted(Permission);
+
+    impl Granted {
+        pub(crate) fn dir(dir: &Path) -> Granted {
+            let p = Permission::Fs(FsAccess::Write, dir.canonicalize().unwrap().display().to_string());
+            PERMISSIONS_MANAGER.lock().unwrap().grant(p.clone());
+            Granted(p)
+        }
+    }
+
+    impl Drop for Granted {
+        fn drop(&mut self) {
+            let mut guard = PERMISSIONS_MANAGER.lock().unwrap_or_else(|e| e.into_inner());
+            let permissions = &mut *guard;
+            for granted in [&mut permissions.allowed, &mut permissions.implicit] {
+                if let Some(i) = granted.iter().position(|p| p == &self.0) {
+                    granted.remove(i);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fs_rules() {
+        let dir = std::env::temp_dir().join("proj-covers");
+        let dir = dir.display().to_string();
+        let write = fs(FsAccess::Write, &dir);
+        assert!(write.covers(&fs(FsAccess::Write, &dir), false));
+        assert!(write.covers(&fs(FsAccess::Read, &format!("{}/a/b", dir)), false));
+        assert!(!write.covers(&fs(FsAccess::Write, &format!("{}x", dir)), false));
+
+        let read = fs(FsAccess::Read, &dir);
+        assert!(read.covers(&fs(FsAccess::Read, &format!("{}/a", dir)), false));
+        assert!(!read.covers(&fs(FsAccess::Write, &format!("{}/a", dir)), false));
+        // denying reads denies writes too, denying writes still allows reads
+        assert!(read.covers(&fs(FsAccess::Write, &format!("{}/a", dir)), true));
+        assert!(!write.covers(&fs(FsAccess::Read, &format!("{}/a", dir)), true));
+    }
+
+    #[test]
+    fn old_fs_permissions_still_load() {
+        let old = serde_json::from_str::<Permission>(r#"{"Fs":"/etc"}"#).unwrap();
+        assert_eq!(old, fs(FsAccess::Write, "/etc"));
+        let read = fs(FsAccess::Read, "/etc");
+        let json = serde_json::to_string(&read).unwrap();
+        assert_eq!(serde_json::from_str::<Permission>(&json).unwrap(), read);
+        let policy = toml::from_str::<policy::PolicyFile>(
+            "allowed = [{ Fs = \"/tmp\" }]\ndenied = [{ Fs = [\"Read\", \"/etc\"] }]",
+        )
+        .unwrap();
+        assert_eq!(policy.allowed, vec![fs(FsAccess::Write, "/tmp")]);
+        assert_eq!(policy.denied, vec![read]);
+    }
+
+    #[test]
+    fn http_rules() {
+        let rule = Permission::Http("api.example.com".to_string());
+        assert!(rule.covers(&Permission::Http("https://api.example.com:443/repos".to_string()), false));
+        assert!(!rule.covers(&Permission::Http("https://example.com:443/".to_string()), false));
+        assert_eq!(
+            Permission::Http("https://api.example.com:443/repos".to_string()).default_rule(),
+            Permission::Http("https://api.example.com:443".to_string())
+        );
+    }
+
+    #[test]
+    fn command_rules() {
+        let git = Permission::Command("git".to_string());
+        assert!(git.covers(&Permission::Command("git status".to_string()), false));
+        assert!(!git.covers(&Permission::Command("gitk".to_string()), false));
+        let commit = Permission::Command("git commit -m 'a b'".to_string());
+        assert!(commit.covers(&Permission::Command("git commit -m \"a b\" --amend".to_string()), false));
+        assert!(!commit.covers(&Permission::Command("git commit -m a b".to_string()), false));
+        assert!(!git.covers(&Permission::Shell("git status".to_string()), false));
+    }
+
+    #[test]
+    fn shell_rules() {
+        let rule = Permission::Shell("make build".to_string());
+        assert!(rule.covers(&Permission::Shell(" make build ".to_string()), false));
+        assert!(!rule.covers(&Permission::Shell("make build; rm -rf ~".to_string()), false));
+    }
+
+    #[test]
+    fn script_rules() {
+        let rule = Permission::Script("build".to_string());
+        assert!(rule.covers(&Permission::Script("build".to_string()), false));
+        assert!(!rule.covers(&Permission::Script("build-release".to_string()), false));
+        assert!(!rule.covers(&Permission::Script("bui".to_string()), false));
+    }
+
+    #[test]
+    fn builtin_script_rules() {
+        let rule = Permission::Script("build".to_string());
+        assert!(rule.covers(&Permission::Script(format!("{}build", PREFIX)), false));
+        let builtin = Permission::Script(format!("{}build", PREFIX));
+        assert!(builtin.covers(&Permission::Script(format!("{}build", PREFIX)), false));
+        assert!(!builtin.covers(&Permission::Script("build".to_string()), false));
+    }
+}
@@ -0,0 +1,54 @@
+use std::{fs, io::IsTerminal, path::Path};
+
+use clap::ArgEnum;
+use serde::{Deserialize, Serialize};
+
+use super::Permission;
+
+/// how requests which aren't already allowed or denied get decided
+#[derive(ArgEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PermissionPolicy {
+    /// ask the user every time
+    Interactive,
+    /// refuse anything not already allowed
+    Deny,
+    /// grant everything
+    Allow,
+    /// only what is listed in the policy file, nothing is remembered or asked
+    PolicyFile,
+}
+
+impl Default for PermissionPolicy {
+    fn default() -> Self {
+        // there is nobody to answer a prompt on ci / headless boxes
+        if std::io::stdin().is_terminal() && std::io::stderr().is_terminal() {
+            PermissionPolicy::Interactive
+        } else {
+            PermissionPolicy::Deny
+        }
+    }
+}
+
+/// the rules read from a policy file, e.g.
+/// ```toml
+/// allowed = [{ Http = "api.github.com" }]
+/// denied = [{ Fs = ["Read", "/etc"] }]
+/// ```
+/// `{ Fs = "/etc" }` is still read, as write access.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PolicyFile {
+    #[serde(default)]
+    pub allowed: Vec<Permission>,
+    #[serde(default)]
+    pub denied: Vec<Permission>,
+}
+
+impl PolicyFile {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("unable to read policy file {} : {}", path.display(), e))?;
+        toml::from_str(&content)
+            .map_err(|e| format!("unable to parse policy file {} : {}", path.display(), e))
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    fmt,
+    io::{self, BufRead, Write},
+    sync::Arc,
+};
+
+use super::Permission;
+
+pub enum PromptAnswer {
+    AllowOnce,
+    // the rule to remember, may be a prefix of what was asked for
+    AllowAlways(Permission),
+    DenyOnce,
+    DenyAlways(Permission),
+}
+
+pub trait PermissionPrompt: Send + Sync {
+    fn ask(&self, p: &Permission) -> PromptAnswer;
+    /// a single approval for everything in a script's manifest
+    fn ask_all(&self, ps: &[Permission]) -> bool;
+}
+
+fn manifest_text(ps: &[Permission]) -> String {
+    ps.iter()
+        .map(|p| format!("  - {}", p))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// asks on stdin / stderr, works over ssh and without a display
+pub struct TerminalPrompt;
+
+impl TerminalPrompt {
+    pub(crate) fn read_line(question: &str) -> Option<String> {
+        eprint!("{}", question);
+        io::stderr().flush().ok()?;
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim().to_string()),
+        }
+    }
+
+    fn ask_prefix(p: &Permission) -> Permission {
+        let rule = p.default_rule();
+        let prefix = Self::read_line(&format!("rule to remember [{}] : ", rule.value()))
+            .filter(|x| !x.is_empty());
+        match prefix {
+            Some(prefix) => p.with_value(prefix),
+            None => rule,
+        }
+    }
+}
+
+impl PermissionPrompt for TerminalPrompt {
+    fn ask(&self, p: &Permission) -> PromptAnswer {
+        eprintln!("The script wants to access {}.", p);
+        loop {
+            let answer = match Self::read_line(
+                "[o] allow once, [a] allow always, [d] deny once, [n] deny always : ",
+            ) {
+                Some(answer) => answer,
+                // stdin closed, nobody is there to answer
+                None => return PromptAnswer::DenyOnce,
+            };
+            match answer.as_str() {
+                "o" => return PromptAnswer::AllowOnce,
+                "a" => return PromptAnswer::AllowAlways(Self::ask_prefix(p)),
+                "d" => return PromptAnswer::DenyOnce,
+                "n" => return PromptAnswer::DenyAlways(Self::ask_prefix(p)),
+                _ => eprintln!("unknown answer \"{}\"", answer),
+            }
+        }
+    }
+
+    fn ask_all(&self, ps: &[Permission]) -> bool {
+        eprintln!("The script wants to access\n{}", manifest_text(ps));
+        matches!(
+            Self::read_line("grant all of these? [y/N] : ").as_deref(),
+            Some("y") | Some("Y")
+        )
+    }
+}
+
+#[cfg(feature = "native-dialogs")]
+pub struct DialogPrompt;
+
+#[cfg(feature = "native-dialogs")]
+impl PermissionPrompt for DialogPrompt {
+    fn ask(&self, p: &Permission) -> PromptAnswer {
+        let rule = p.default_rule();
+        match native_dialog::MessageDialog::new()
+            .set_title("Permission")
+            .set_text(&format!(
+                "The script wants to access\n{}.\ndo you want to grant access to\n{}?",
+                p, rule
+            ))
+            .show_confirm()
+        {
+            Ok(true) => PromptAnswer::AllowAlways(rule),
+            Ok(false) => PromptAnswer::DenyAlways(rule),
+            // no dialog backend available, fall back to the terminal
+            Err(_) => TerminalPrompt.ask(p),
+        }
+    }
+
+    fn ask_all(&self, ps: &[Permission]) -> bool {
+        match native_dialog::MessageDialog::new()
+            .set_title("Permissions")
+            .set_text(&format!(
+                "The script wants to access\n{}\ndo you want to grant access?",
+                manifest_text(ps)
+            ))
+            .show_confirm()
+        {
+            Ok(allowed) => allowed,
+            Err(_) => TerminalPrompt.ask_all(ps),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Prompter(pub Arc<dyn PermissionPrompt>);
+
+impl fmt::Debug for Prompter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Prompter")
+    }
+}
+
+#[cfg(feature = "native-dialogs")]
+pub(crate) fn dialogs_available() -> bool {
+    // dialogs would pop up on the wrong machine (or nowhere) over ssh
+    let remote = std::env::var_os("SSH_CONNECTION").is_some();
+    let headless = cfg!(target_os = "linux")
+        && std::env::var_os("DISPLAY").is_none()
+        && std::env::var_os("WAYLAND_DISPLAY").is_none();
+    !remote && !headless
+}
+
+impl Default for Prompter {
+    #[cfg(feature = "native-dialogs")]
+    fn default() -> Self {
+        if dialogs_available() {
+            Prompter(Arc::new(DialogPrompt))
+        } else {
+            Prompter(Arc::new(TerminalPrompt))
+        }
+    }
+    #[cfg(not(feature = "native-dialogs"))]
+    fn default() -> Self {
+        Prompter(Arc::new(TerminalPrompt))
+    }
+}
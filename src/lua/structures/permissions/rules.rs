@@ -0,0 +1,200 @@
+/// a http permission rule, written as `[scheme://]host[:port][/path]`.
+/// the host may start with `*.` to match any subdomain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpRule {
+    pub scheme: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+    pub path: Option<String>,
+}
+
+impl HttpRule {
+    pub fn parse(s: &str) -> Option<Self> {
+        let (scheme, rest) = match s.split_once("://") {
+            Some((scheme, rest)) => (Some(scheme.to_lowercase()), rest),
+            None => (None, s),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], Some(rest[i..].trim_end_matches('/').to_string())),
+            None => (rest, None),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port.parse::<u16>().ok()?)),
+            None => (authority, None),
+        };
+        if host.is_empty() {
+            return None;
+        }
+        Some(HttpRule {
+            scheme,
+            host: host.to_lowercase(),
+            port,
+            path: path.filter(|p| !p.is_empty()),
+        })
+    }
+
+    /// whether `req` (a fully specified url) falls under this rule
+    pub fn matches(&self, req: &HttpRule) -> bool {
+        let scheme = match &self.scheme {
+            Some(scheme) => req.scheme.as_ref() == Some(scheme),
+            None => true,
+        };
+        let host = match self.host.strip_prefix('*') {
+            // "*.example.com" matches "a.example.com" but not "example.com" or "evilexample.com"
+            Some(suffix) if suffix.starts_with('.') => req.host.ends_with(suffix),
+            _ => req.host == self.host,
+        };
+        let port = match self.port {
+            Some(port) => req.port == Some(port),
+            None => true,
+        };
+        let path = match (&self.path, &req.path) {
+            (Some(rule), Some(path)) => {
+                path == rule || path.starts_with(&format!("{}/", rule))
+            }
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        scheme && host && port && path
+    }
+
+    /// the same rule without its path, what gets remembered when a request is granted
+    pub fn origin(&self) -> String {
+        let mut origin = String::new();
+        if let Some(scheme) = &self.scheme {
+            origin.push_str(&format!("{}://", scheme));
+        }
+        origin.push_str(&self.host);
+        if let Some(port) = self.port {
+            origin.push_str(&format!(":{}", port));
+        }
+        origin
+    }
+}
+
+/// a command line as words, `'...'` and `"..."` group words with spaces in them.
+/// it's what command permissions are written in, not a shell, nothing else is interpreted.
+pub fn split_words(s: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quote = None;
+    for c in s.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+fn quote_word(word: &str) -> String {
+    if !word.is_empty() && !word.contains(|c: char| c.is_whitespace() || c == '\'' || c == '"') {
+        return word.to_string();
+    }
+    // a quote of the same kind can't be escaped, it's closed, quoted the other way and reopened
+    let mut quoted = String::from("'");
+    for c in word.chars() {
+        if c == '\'' {
+            quoted.push_str("'\"'\"'");
+        } else {
+            quoted.push(c);
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// the inverse of `split_words`, so every argument stays one word
+pub fn join_words(args: &[String]) -> String {
+    args.iter()
+        .map(|a| quote_word(a))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_http_rules() {
+        assert_eq!(
+            HttpRule::parse("HTTPS://Example.com:8080/a/b/"),
+            Some(HttpRule {
+                scheme: Some("https".to_string()),
+                host: "example.com".to_string(),
+                port: Some(8080),
+                path: Some("/a/b".to_string()),
+            })
+        );
+        assert_eq!(
+            HttpRule::parse("example.com/"),
+            Some(HttpRule {
+                scheme: None,
+                host: "example.com".to_string(),
+                port: None,
+                path: None,
+            })
+        );
+        assert_eq!(HttpRule::parse("example.com:http"), None);
+        assert_eq!(HttpRule::parse("https:///a"), None);
+        assert_eq!(HttpRule::parse(""), None);
+    }
+
+    #[test]
+    fn match_http_rules() {
+        let req = |s: &str| HttpRule::parse(s).unwrap();
+        let rule = req("*.example.com");
+        assert!(rule.matches(&req("https://a.example.com:443/x")));
+        assert!(!rule.matches(&req("https://example.com:443/x")));
+        assert!(!rule.matches(&req("https://evilexample.com:443/x")));
+
+        let rule = req("https://api.example.com/v1");
+        assert!(rule.matches(&req("https://api.example.com:443/v1")));
+        assert!(rule.matches(&req("https://api.example.com:443/v1/users")));
+        assert!(!rule.matches(&req("https://api.example.com:443/v10")));
+        assert!(!rule.matches(&req("http://api.example.com:80/v1")));
+
+        let rule = req("api.example.com:8080");
+        assert!(!rule.matches(&req("https://api.example.com:443/")));
+        assert_eq!(req("https://api.example.com:8080/v1").origin(), "https://api.example.com:8080");
+    }
+
+    #[test]
+    fn split_command_lines() {
+        assert_eq!(
+            split_words("git  commit -m \"a b\" 'c \"d\"' ''"),
+            vec!["git", "commit", "-m", "a b", "c \"d\"", ""]
+        );
+        assert_eq!(split_words("  "), Vec::<String>::new());
+        assert_eq!(split_words("a\"b c\"d"), vec!["ab cd"]);
+    }
+
+    #[test]
+    fn joined_words_split_back() {
+        let args = ["git", "", "a b", "it's", "say \"hi\"", "both ' and \""]
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(split_words(&join_words(&args)), args);
+        assert_eq!(join_words(&args[..1]), "git");
+    }
+}
@@ -0,0 +1,122 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{Permission, Permissions};
+
+/// decisions remembered for a single script, only valid while the script's source hash matches.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StoredPermissions {
+    pub hash: String,
+    pub allowed: Vec<Permission>,
+    pub denied: Vec<Permission>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct PermissionStore {
+    #[serde(skip)]
+    path: PathBuf,
+    // set when a broken file couldn't be moved aside, saving would overwrite it
+    #[serde(skip)]
+    read_only: bool,
+    pub scripts: HashMap<String, StoredPermissions>,
+}
+
+pub fn hash_source(code: &str) -> String {
+    Sha256::digest(code.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+impl PermissionStore {
+    /// loads the store from `<config>/permissions.json`, a missing file gives an empty store.
+    /// a broken one is moved aside to `permissions.json.bak` so saving doesn't lose it.
+    pub fn load(config_dir: &Path) -> Self {
+        let path = config_dir.join("permissions.json");
+        let mut read_only = false;
+        let mut store = match fs::read_to_string(&path) {
+            Ok(s) => match serde_json::from_str::<PermissionStore>(&s) {
+                Ok(store) => store,
+                Err(e) => {
+                    let backup = path.with_extension("json.bak");
+                    eprintln!("unable to read {} : {}", path.display(), e);
+                    match fs::rename(&path, &backup) {
+                        Ok(_) => eprintln!("it was moved to {}", backup.display()),
+                        Err(e) => {
+                            eprintln!("unable to move {} : {}", path.display(), e);
+                            read_only = true;
+                        }
+                    }
+                    PermissionStore::default()
+                }
+            },
+            Err(_) => PermissionStore::default(),
+        };
+        store.path = path;
+        store.read_only = read_only;
+        store
+    }
+
+    pub fn save(&self) -> std::io::Result<()> {
+        if self.read_only {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} couldn't be read, fix or remove it", self.path.display()),
+            ));
+        }
+        let content = serde_json::to_string_pretty(self)?;
+        let mut file = File::create(&self.path)?;
+        file.write_all(content.as_bytes())
+    }
+
+    /// returns what was remembered for `name`, dropping the entry if the script has changed since.
+    pub fn get(&mut self, name: &str, hash: &str) -> Option<&StoredPermissions> {
+        if let Some(stored) = self.scripts.get(name) && stored.hash != hash {
+            self.scripts.remove(name);
+        }
+        self.scripts.get(name)
+    }
+
+    /// forgets the rules of `name` with the given value, or all of them.
+    /// returns how many rules were forgotten.
+    pub fn revoke(&mut self, name: &str, value: Option<&str>) -> usize {
+        let Some(stored) = self.scripts.get_mut(name) else {
+            return 0;
+        };
+        let count = stored.allowed.len() + stored.denied.len();
+        match value {
+            Some(value) => {
+                stored.allowed.retain(|p| p.value() != value);
+                stored.denied.retain(|p| p.value() != value);
+                count - stored.allowed.len() - stored.denied.len()
+            }
+            None => {
+                self.scripts.remove(name);
+                count
+            }
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.scripts.clear();
+    }
+
+    pub fn set(&mut self, name: &str, hash: &str, permissions: &Permissions) {
+        let (allowed, denied) = permissions.persistent();
+        self.scripts.insert(
+            name.to_string(),
+            StoredPermissions {
+                hash: hash.to_string(),
+                allowed,
+                denied,
+            },
+        );
+    }
+}
@@ -0,0 +1,408 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+    time::Duration,
+};
+
+use mlua::{LuaSerdeExt, UserData, Value};
+use path_absolutize::Absolutize;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader},
+    process::{Child, ChildStderr, ChildStdout, Command},
+    sync::Mutex,
+};
+
+use super::{
+    effects::{self, Effect, Snapshot},
+    fs::is_path_allowed,
+    permissions::{rules::join_words, FsAccess, Permission, PERMISSIONS_MANAGER},
+};
+
+/// the `process` global, holds the project dir which is used as the default cwd
+#[derive(Clone)]
+pub struct LuaProcess(pub String);
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct ProcessOptions {
+    cwd: Option<String>,
+    env: HashMap<String, String>,
+    // variables copied from proj's own environment, everything else is cleared
+    inherit_env: Option<Vec<String>>,
+    // seconds
+    timeout: Option<f64>,
+    shell: bool,
+    stream: bool,
+    stdin: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ProcessResult {
+    status: Option<i32>,
+    success: bool,
+    timed_out: bool,
+    stdout: String,
+    stderr: String,
+}
+
+/// `name` found in proj's own PATH, so a script setting `env.PATH` can't swap the program
+/// which was allowed for one of its own
+fn find_program(name: &str, cwd: &Path) -> mlua::Result<PathBuf> {
+    let path = Path::new(name);
+    if path.components().count() > 1 {
+        return Ok(cwd.join(path).absolutize()?.to_path_buf());
+    }
+    let extensions = if cfg!(windows) {
+        std::env::var("PATHEXT")
+            .unwrap_or_else(|_| ".EXE;.CMD;.BAT;.COM".to_string())
+            .split(';')
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+    } else {
+        vec![]
+    };
+    let dirs = std::env::var_os("PATH").unwrap_or_default();
+    for dir in std::env::split_paths(&dirs) {
+        let candidate = dir.join(name);
+        if is_executable(&candidate) {
+            return Ok(candidate);
+        }
+        for extension in extensions.iter() {
+            let candidate = dir.join(format!("{}{}", name, extension));
+            if is_executable(&candidate) {
+                return Ok(candidate);
+            }
+        }
+    }
+    Err(mlua::Error::RuntimeError(format!(
+        "unable to find \"{}\" in PATH",
+        name
+    )))
+}
+
+fn is_executable(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        path.metadata()
+            .map_or(false, |m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+    }
+    #[cfg(not(unix))]
+    path.is_file()
+}
+
+/// variables which change what gets loaded into a program, they'd get around the command rules
+fn check_env(env: &HashMap<String, String>, shell: bool) -> mlua::Result<()> {
+    for name in env.keys() {
+        let upper = name.to_uppercase();
+        // the shell looks commands up itself
+        if upper.starts_with("LD_") || upper.starts_with("DYLD_") || (shell && upper == "PATH") {
+            return Err(mlua::Error::RuntimeError(format!(
+                "the environment variable {} can't be set for a command",
+                name
+            )));
+        }
+    }
+    Ok(())
+}
+
+impl LuaProcess {
+    /// the command and the dir it runs in
+    fn command(&self, args: &[String], options: &ProcessOptions) -> mlua::Result<(Command, PathBuf)> {
+        if args.is_empty() {
+            return Err(mlua::Error::RuntimeError("no command given".to_string()));
+        }
+        // a shell line can't be matched by prefix, `git; rm -rf ~` starts with `git` too
+        let permission = if options.shell {
+            Permission::Shell(args.join(" "))
+        } else {
+            Permission::Command(join_words(args))
+        };
+        PERMISSIONS_MANAGER
+            .lock()
+            .unwrap()
+            .ask_for_access(&permission)?;
+        check_env(&options.env, options.shell)?;
+
+        let cwd = Path::new(options.cwd.as_ref().unwrap_or(&self.0))
+            .absolutize()?
+            .to_path_buf();
+        is_path_allowed(&cwd, FsAccess::Write)?;
+
+        let mut command = if options.shell {
+            let mut command = if cfg!(windows) {
+                let mut c = Command::new(find_program("cmd", &cwd)?);
+                c.arg("/C");
+                c
+            } else {
+                let mut c = Command::new(find_program("sh", &cwd)?);
+                c.arg("-c");
+                c
+            };
+            command.arg(args.join(" "));
+            command
+        } else {
+            let mut command = Command::new(find_program(&args[0], &cwd)?);
+            command.args(&args[1..]);
+            command
+        };
+
+        command.current_dir(&cwd).env_clear();
+        let inherit = options
+            .inherit_env
+            .clone()
+            .unwrap_or_else(|| vec!["PATH".to_string()]);
+        for name in inherit {
+            if let Some(v) = std::env::var_os(&name) {
+                command.env(name, v);
+            }
+        }
+        command
+            .envs(&options.env)
+            .stdin(if options.stdin.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        Ok((command, cwd))
+    }
+}
+
+fn parse_options(lua: &mlua::Lua, options: Option<Value>) -> mlua::Result<ProcessOptions> {
+    match options {
+        Some(Value::Nil) | None => Ok(ProcessOptions::default()),
+        Some(v) => lua.from_value(v),
+    }
+}
+
+/// written from its own task, a child filling its stdout before reading all of stdin
+/// would otherwise block both sides
+fn write_stdin(child: &mut Child, stdin: Option<String>) {
+    if let Some(input) = stdin && let Some(mut pipe) = child.stdin.take() {
+        tokio::spawn(async move {
+            // the child may exit without reading everything, that's up to it
+            let _ = pipe.write_all(input.as_bytes()).await;
+            // dropping the pipe closes it
+        });
+    }
+}
+
+/// the next line without its newline, invalid utf-8 is replaced rather than ending the output
+async fn read_line<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> std::io::Result<Option<String>> {
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line).await? == 0 {
+        return Ok(None);
+    }
+    if line.last() == Some(&b'\n') {
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+    }
+    Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+}
+
+/// reads every remaining line, `echo` prints them as they come in (to stderr when true)
+async fn collect<R: AsyncRead + Unpin>(reader: &mut BufReader<R>, echo: Option<bool>) -> String {
+    let mut output = String::new();
+    while let Ok(Some(line)) = read_line(reader).await {
+        match echo {
+            Some(false) => println!("{}", line),
+            Some(true) => eprintln!("{}", line),
+            None => {}
+        }
+        output.push_str(&line);
+        output.push('\n');
+    }
+    output
+}
+
+/// records the command's dir so what it changes can be rolled back
+fn snapshot(cwd: &Path, args: &[String]) -> mlua::Result<Snapshot> {
+    let snapshot = effects::before_command(cwd)?.ok_or_else(|| {
+        mlua::Error::RuntimeError("commands aren't run in a dry run".to_string())
+    })?;
+    if !snapshot.complete {
+        eprintln!(
+            "warning: {} is too big to keep, changes made to it by \"{}\" can't all be rolled back",
+            cwd.display(),
+            args.join(" ")
+        );
+    }
+    Ok(snapshot)
+}
+
+/// waits for the child, killing it if it runs past the timeout
+async fn wait(child: &mut Child, timeout: Option<Duration>) -> mlua::Result<(Option<i32>, bool)> {
+    let status = match timeout {
+        Some(d) => match tokio::time::timeout(d, child.wait()).await {
+            Ok(status) => status?,
+            Err(_) => {
+                child.kill().await?;
+                return Ok((None, true));
+            }
+        },
+        None => child.wait().await?,
+    };
+    Ok((status.code(), false))
+}
+
+/// a process started by `process:spawn`
+#[derive(Clone)]
+pub struct LuaChild {
+    child: Arc<Mutex<Child>>,
+    stdout: Arc<Mutex<BufReader<ChildStdout>>>,
+    stderr: Arc<Mutex<BufReader<ChildStderr>>>,
+    timeout: Option<Duration>,
+    pid: Option<u32>,
+    // taken by `wait`, what the child created is journaled once it's done
+    snapshot: Arc<Mutex<Option<Snapshot>>>,
+}
+
+impl UserData for LuaChild {
+    fn add_fields<'lua, F: mlua::UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("pid", |_l, t| Ok(t.pid));
+        fields.add_meta_field_with("__name", |_lua| Ok("LuaChild".to_string()));
+    }
+
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_async_method("readLine", |_l, t, ()| async move {
+            Ok(read_line(&mut *t.stdout.lock().await).await?)
+        });
+        methods.add_async_method("readErrorLine", |_l, t, ()| async move {
+            Ok(read_line(&mut *t.stderr.lock().await).await?)
+        });
+        methods.add_async_method("kill", |_l, t, ()| async move {
+            t.child.lock().await.kill().await?;
+            Ok(())
+        });
+        methods.add_async_method("wait", |l, t, ()| async move {
+            let mut child = t.child.lock().await;
+            let mut stdout = t.stdout.lock().await;
+            let mut stderr = t.stderr.lock().await;
+            // the pipes are drained while waiting so a chatty child can't block on a full pipe
+            let (waited, stdout, stderr) = tokio::join!(
+                wait(&mut child, t.timeout),
+                collect(&mut stdout, None),
+                collect(&mut stderr, None)
+            );
+            if let Some(snapshot) = t.snapshot.lock().await.take() {
+                effects::after_command(snapshot);
+            }
+            let (status, timed_out) = waited?;
+            l.to_value(&ProcessResult {
+                status,
+                success: status == Some(0),
+                timed_out,
+                stdout,
+                stderr,
+            })
+        });
+    }
+}
+
+impl UserData for LuaProcess {
+    fn add_fields<'lua, F: mlua::UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_meta_field_with("__name", |_lua| Ok("LuaProcess".to_string()));
+    }
+
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_async_method(
+            "run",
+            |l, t, (args, options): (Vec<String>, Option<Value>)| async move {
+                let options = parse_options(l, options)?;
+                let (mut command, cwd) = t.command(&args, &options)?;
+                if effects::is_dry_run() {
+                    effects::record(Effect::Command(args.join(" ")));
+                    return l.to_value(&ProcessResult {
+                        status: Some(0),
+                        success: true,
+                        timed_out: false,
+                        stdout: String::new(),
+                        stderr: String::new(),
+                    });
+                }
+                let snapshot = snapshot(&cwd, &args)?;
+                let mut child = command.spawn()?;
+                write_stdin(&mut child, options.stdin.clone());
+
+                let stream = options.stream;
+                let mut stdout = BufReader::new(child.stdout.take().unwrap());
+                let mut stderr = BufReader::new(child.stderr.take().unwrap());
+                let (waited, stdout, stderr) = tokio::join!(
+                    wait(&mut child, options.timeout.map(Duration::from_secs_f64)),
+                    collect(&mut stdout, stream.then(|| false)),
+                    collect(&mut stderr, stream.then(|| true))
+                );
+                effects::after_command(snapshot);
+                let (status, timed_out) = waited?;
+
+                l.to_value(&ProcessResult {
+                    status,
+                    success: status == Some(0),
+                    timed_out,
+                    stdout,
+                    stderr,
+                })
+            },
+        );
+        methods.add_async_method(
+            "spawn",
+            |l, t, (args, options): (Vec<String>, Option<Value>)| async move {
+                let options = parse_options(l, options)?;
+                let (mut command, cwd) = t.command(&args, &options)?;
+                if effects::is_dry_run() {
+                    return Err(mlua::Error::RuntimeError(format!(
+                        "can't spawn \"{}\" in a dry run, use process:run instead",
+                        args.join(" ")
+                    )));
+                }
+                let snapshot = snapshot(&cwd, &args)?;
+                let mut child = command.spawn()?;
+                write_stdin(&mut child, options.stdin.clone());
+
+                Ok(LuaChild {
+                    snapshot: Arc::new(Mutex::new(Some(snapshot))),
+                    pid: child.id(),
+                    stdout: Arc::new(Mutex::new(BufReader::new(child.stdout.take().unwrap()))),
+                    stderr: Arc::new(Mutex::new(BufReader::new(child.stderr.take().unwrap()))),
+                    child: Arc::new(Mutex::new(child)),
+                    timeout: options.timeout.map(Duration::from_secs_f64),
+                })
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loader_variables_are_refused() {
+        let env = |name: &str| HashMap::from([(name.to_string(), "x".to_string())]);
+        assert!(check_env(&env("LD_PRELOAD"), false).is_err());
+        assert!(check_env(&env("DYLD_INSERT_LIBRARIES"), false).is_err());
+        assert!(check_env(&env("PATH"), true).is_err());
+        assert!(check_env(&env("PATH"), false).is_ok());
+        assert!(check_env(&env("RUST_LOG"), true).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn programs_are_found_in_procs_path() {
+        let sh = find_program("sh", Path::new("/")).unwrap();
+        assert!(sh.is_absolute() && sh.ends_with("sh"));
+        assert!(find_program("proj-no-such-program", Path::new("/")).is_err());
+        assert_eq!(
+            find_program("./bin/tool", Path::new("/project")).unwrap(),
+            PathBuf::from("/project/bin/tool")
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+use mlua::{Error, UserData};
+
+#[cfg(feature = "native-dialogs")]
+use super::permissions::prompt::dialogs_available;
+use super::permissions::prompt::TerminalPrompt;
+
+/// the `prompt` global, asks the user questions for scaffolding wizards.
+/// answers given as `--arg key=value` are used instead of asking.
+/// with native-dialogs only `confirm` uses a dialog, native-dialog has no text input or lists,
+/// every other kind of question is asked in the terminal.
+pub struct LuaPrompt {
+    pub interactive: bool,
+    pub answers: HashMap<String, String>,
+    // every key asked for, arguments which are neither params nor in here are mistakes
+    pub used: Mutex<HashSet<String>>,
+}
+
+fn parse_bool(key: &str, s: &str) -> mlua::Result<bool> {
+    match s.to_lowercase().as_str() {
+        "true" | "yes" | "y" | "1" => Ok(true),
+        "false" | "no" | "n" | "0" => Ok(false),
+        _ => Err(Error::RuntimeError(format!(
+            "answer for \"{}\" should be yes or no",
+            key
+        ))),
+    }
+}
+
+fn check_choice(key: &str, choices: &[String], s: &str) -> mlua::Result<String> {
+    // a number picks from the list shown, anything else has to be one of the choices
+    if let Ok(i) = s.parse::<usize>() && i >= 1 && i <= choices.len() {
+        return Ok(choices[i - 1].clone());
+    }
+    if choices.iter().any(|c| c == s) {
+        Ok(s.to_string())
+    } else {
+        Err(Error::RuntimeError(format!(
+            "answer for \"{}\" should be one of {}",
+            key,
+            choices.join(", ")
+        )))
+    }
+}
+
+impl LuaPrompt {
+    /// the answer given on the command line, or the one typed in by the user
+    fn answer(&self, key: &str, question: &str) -> Option<String> {
+        self.used.lock().unwrap().insert(key.to_string());
+        if let Some(answer) = self.answers.get(key) {
+            return Some(answer.clone());
+        }
+        if !self.interactive {
+            return None;
+        }
+        TerminalPrompt::read_line(question).filter(|x| !x.is_empty())
+    }
+
+    /// a missing answer without a default is always an error
+    fn required<T>(&self, key: &str, answer: Option<T>) -> mlua::Result<T> {
+        answer.ok_or_else(|| {
+            if self.interactive {
+                Error::RuntimeError(format!("no answer given for \"{}\"", key))
+            } else {
+                Error::RuntimeError(format!(
+                    "no answer for \"{}\", pass it with --arg {}=...",
+                    key, key
+                ))
+            }
+        })
+    }
+
+    fn print_choices(&self, key: &str, choices: &[String]) {
+        if self.interactive && !self.answers.contains_key(key) {
+            for (i, choice) in choices.iter().enumerate() {
+                eprintln!("  {}) {}", i + 1, choice);
+            }
+        }
+    }
+}
+
+impl UserData for LuaPrompt {
+    fn add_fields<'lua, F: mlua::UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("interactive", |_l, t| Ok(t.interactive));
+        fields.add_meta_field_with("__name", |_lua| Ok("LuaPrompt".to_string()));
+    }
+
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method(
+            "text",
+            |_l, t, (key, message, default): (String, String, Option<String>)| {
+                let question = match &default {
+                    Some(d) => format!("{} [{}] : ", message, d),
+                    None => format!("{} : ", message),
+                };
+                t.required(&key, t.answer(&key, &question).or(default))
+            },
+        );
+        methods.add_method(
+            "confirm",
+            |_l, t, (key, message, default): (String, String, Option<bool>)| {
+                let question = match default {
+                    Some(true) => format!("{} [Y/n] : ", message),
+                    Some(false) => format!("{} [y/N] : ", message),
+                    None => format!("{} [y/n] : ", message),
+                };
+                t.used.lock().unwrap().insert(key.clone());
+                #[cfg(feature = "native-dialogs")]
+                if t.interactive && !t.answers.contains_key(&key) && dialogs_available() {
+                    if let Ok(answer) = native_dialog::MessageDialog::new()
+                        .set_title("proj")
+                        .set_text(&message)
+                        .show_confirm()
+                    {
+                        return Ok(answer);
+                    }
+                }
+                match t.answer(&key, &question) {
+                    Some(answer) => parse_bool(&key, &answer),
+                    None => t.required(&key, default),
+                }
+            },
+        );
+        methods.add_method(
+            "select",
+            |_l, t, (key, message, choices, default): (String, String, Vec<String>, Option<String>)| {
+                t.print_choices(&key, &choices);
+                let question = match &default {
+                    Some(d) => format!("{} [{}] : ", message, d),
+                    None => format!("{} : ", message),
+                };
+                let answer = t.required(&key, t.answer(&key, &question).or(default))?;
+                check_choice(&key, &choices, &answer)
+            },
+        );
+        methods.add_method(
+            "multiselect",
+            |_l, t, (key, message, choices, default): (String, String, Vec<String>, Option<Vec<String>>)| {
+                t.print_choices(&key, &choices);
+                let question = format!("{} (comma separated) : ", message);
+                match t.answer(&key, &question) {
+                    Some(answer) => answer
+                        .split(',')
+                        .map(|x| x.trim())
+                        .filter(|x| !x.is_empty())
+                        .map(|x| check_choice(&key, &choices, x))
+                        .collect::<mlua::Result<Vec<_>>>(),
+                    None if t.interactive => Ok(default.unwrap_or_default()),
+                    None => t.required(&key, default),
+                }
+            },
+        );
+        methods.add_method("password", |_l, t, (key, message): (String, String)| {
+            t.used.lock().unwrap().insert(key.clone());
+            if let Some(answer) = t.answers.get(&key) {
+                return Ok(answer.clone());
+            }
+            if !t.interactive {
+                return t.required(&key, None);
+            }
+            Ok(rpassword::prompt_password(format!("{} : ", message))?)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mlua::Lua;
+
+    use super::*;
+
+    fn prompt(answers: &[(&str, &str)]) -> LuaPrompt {
+        LuaPrompt {
+            interactive: false,
+            answers: answers.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            used: Mutex::new(HashSet::new()),
+        }
+    }
+
+    #[test]
+    fn answers_are_checked() {
+        assert!(parse_bool("a", "Yes").unwrap());
+        assert!(!parse_bool("a", "0").unwrap());
+        assert!(parse_bool("a", "maybe").is_err());
+
+        let choices = vec!["rust".to_string(), "go".to_string()];
+        assert_eq!(check_choice("lang", &choices, "go").unwrap(), "go");
+        assert_eq!(check_choice("lang", &choices, "1").unwrap(), "rust");
+        assert!(check_choice("lang", &choices, "3").is_err());
+        assert!(check_choice("lang", &choices, "c").is_err());
+    }
+
+    #[test]
+    fn arguments_answer_without_asking() {
+        let lua = Lua::new();
+        lua.globals()
+            .set("prompt", prompt(&[("name", "app"), ("lang", "2"), ("features", "a, c"), ("ci", "no")]))
+            .unwrap();
+        lua.load(
+            r#"
+            assert(prompt.interactive == false)
+            assert(prompt:text("name", "Name") == "app")
+            assert(prompt:text("license", "License", "MIT") == "MIT")
+            assert(prompt:select("lang", "Language", {"rust", "go"}) == "go")
+            local features = prompt:multiselect("features", "Features", {"a", "b", "c"})
+            assert(#features == 2 and features[1] == "a" and features[2] == "c")
+            assert(prompt:confirm("ci", "CI ?", true) == false)
+            assert(prompt:confirm("git", "Git ?", true) == true)
+            assert(pcall(prompt.text, prompt, "author", "Author") == false)
+            "#,
+        )
+        .exec()
+        .unwrap();
+
+        let prompt = lua.globals().get::<_, mlua::AnyUserData>("prompt").unwrap();
+        let prompt = prompt.borrow::<LuaPrompt>().unwrap();
+        let used = prompt.used.lock().unwrap();
+        for key in ["name", "license", "lang", "features", "ci", "git", "author"] {
+            assert!(used.contains(key), "{} wasn't marked as used", key);
+        }
+    }
+}
@@ -1,43 +1,306 @@
 use std::{
     collections::HashMap,
+    path::PathBuf,
     sync::{Arc, Mutex},
 };
 
-use mlua::{Function, RegistryKey, UserData};
+use mlua::{AnyUserData, Function, Lua, LuaSerdeExt, MultiValue, RegistryKey, Table, UserData, Value};
+use semver::Version;
+use serde::Serialize;
 
-#[derive(Default, Clone, Debug)]
+use crate::lua::builtin::PREFIX;
+
+use super::{
+    conflicts::ConflictPolicy,
+    params::ScriptParam,
+    permissions::{FsAccess, Permission, PERMISSIONS_MANAGER},
+};
+
+/// how deep `scriptManager:run` calls may nest
+pub const MAX_CALL_DEPTH: usize = 16;
+
+#[derive(Default, Clone, Debug, Serialize)]
 pub struct LuaScript {
     pub name: String,
+    pub description: Option<String>,
+    // semver
+    pub version: Option<String>,
+    pub author: Option<String>,
+    pub tags: Vec<String>,
+    // the oldest proj the script works with, checked when it's added
+    pub min_proj_version: Option<String>,
+    pub homepage: Option<String>,
+    // sha256 of the source the script was loaded from
+    pub hash: String,
+    // the file which defined the script, None when embedded in proj
+    pub file: Option<PathBuf>,
+    // everything the script may access, `$PROJECT` stands for the project dir
+    pub permissions: Option<Vec<Permission>>,
+    // passed to invoke_fn as a table, filled from `--arg key=value`
+    pub params: Vec<ScriptParam>,
+    // what to do with existing files, `--on-conflict` takes precedence
+    pub on_conflict: Option<ConflictPolicy>,
+    // handed to the manager by `scriptManager:add`
+    #[serde(skip)]
+    pub invoke_fn: Option<Arc<RegistryKey>>,
+    // `scriptManager:add` keeps a copy, fields set on the lua value afterwards would be lost
+    #[serde(skip)]
+    pub added: bool,
+}
+
+impl LuaScript {
+    fn check_not_added(&self, field: &str) -> mlua::Result<()> {
+        if self.added {
+            return Err(mlua::Error::RuntimeError(format!(
+                "script \"{}\" was already added, set {} before calling scriptManager:add",
+                self.name, field
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn parse_version(field: &str, v: Option<String>) -> mlua::Result<Option<String>> {
+    if let Some(v) = &v && let Err(e) = Version::parse(v) {
+        return Err(mlua::Error::RuntimeError(format!(
+            "{} \"{}\" isn't a valid version : {}",
+            field, v, e
+        )));
+    }
+    Ok(v)
+}
+
+fn parse_manifest(t: Table) -> mlua::Result<Vec<Permission>> {
+    let mut manifest = Vec::new();
+    for pair in t.pairs::<String, Value>() {
+        let (kind, values) = pair?;
+        let values = match values {
+            Value::String(s) => vec![s.to_str()?.to_string()],
+            Value::Table(t) => t.sequence_values::<String>().collect::<mlua::Result<Vec<_>>>()?,
+            _ => {
+                return Err(mlua::Error::RuntimeError(format!(
+                    "permissions.{} should be a list of strings",
+                    kind
+                )))
+            }
+        };
+        for v in values {
+            manifest.push(match kind.as_str() {
+                "fs" => Permission::Fs(FsAccess::Write, v),
+                "fs_read" => Permission::Fs(FsAccess::Read, v),
+                "http" => Permission::Http(v),
+                "script" => Permission::Script(v),
+                "command" => Permission::Command(v),
+                "shell" => Permission::Shell(v),
+                _ => {
+                    return Err(mlua::Error::RuntimeError(format!(
+                        "unknown permission kind \"{}\"",
+                        kind
+                    )))
+                }
+            });
+        }
+    }
+    Ok(manifest)
 }
 impl UserData for LuaScript {
     fn add_fields<'lua, F: mlua::UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_field_method_get("name", |_lua, this| Ok(this.name.clone()));
+        fields.add_field_method_set("description", |_lua, this, v: Option<String>| {
+            this.check_not_added("description")?;
+            this.description = v;
+            Ok(())
+        });
+        fields.add_field_method_get("description", |_lua, this| Ok(this.description.clone()));
+        fields.add_field_method_set("version", |_lua, this, v: Option<String>| {
+            this.check_not_added("version")?;
+            this.version = parse_version("version", v)?;
+            Ok(())
+        });
+        fields.add_field_method_get("version", |_lua, this| Ok(this.version.clone()));
+        fields.add_field_method_set("author", |_lua, this, v: Option<String>| {
+            this.check_not_added("author")?;
+            this.author = v;
+            Ok(())
+        });
+        fields.add_field_method_get("author", |_lua, this| Ok(this.author.clone()));
+        fields.add_field_method_set("tags", |_lua, this, v: Vec<String>| {
+            this.check_not_added("tags")?;
+            this.tags = v;
+            Ok(())
+        });
+        fields.add_field_method_get("tags", |_lua, this| Ok(this.tags.clone()));
+        fields.add_field_method_set("min_proj_version", |_lua, this, v: Option<String>| {
+            this.check_not_added("min_proj_version")?;
+            this.min_proj_version = parse_version("min_proj_version", v)?;
+            Ok(())
+        });
+        fields.add_field_method_get("min_proj_version", |_lua, this| {
+            Ok(this.min_proj_version.clone())
+        });
+        fields.add_field_method_set("homepage", |_lua, this, v: Option<String>| {
+            this.check_not_added("homepage")?;
+            this.homepage = v;
+            Ok(())
+        });
+        fields.add_field_method_get("homepage", |_lua, this| Ok(this.homepage.clone()));
         fields.add_field_method_set("invoke_fn", |lua, this, f: Function<'_>| {
-            SCRIPTS_MANAGER
-                .lock()
-                .unwrap()
-                .fns
-                .insert(this.name.to_string(), Some(lua.create_registry_value(f)?));
+            this.check_not_added("invoke_fn")?;
+            this.invoke_fn = Some(Arc::new(lua.create_registry_value(f)?));
+            Ok(())
+        });
+        fields.add_field_method_set("permissions", |_lua, this, t: Table| {
+            this.check_not_added("permissions")?;
+            this.permissions = Some(parse_manifest(t)?);
+            Ok(())
+        });
+        fields.add_field_method_get("permissions", |lua, this| {
+            lua.to_value(&this.permissions)
+        });
+        fields.add_field_method_set("params", |lua, this, v: Value| {
+            this.check_not_added("params")?;
+            let params: Vec<ScriptParam> = lua.from_value(v)?;
+            for param in params.iter() {
+                param.check().map_err(mlua::Error::RuntimeError)?;
+            }
+            this.params = params;
+            Ok(())
+        });
+        fields.add_field_method_get("params", |lua, this| lua.to_value(&this.params));
+        fields.add_field_method_set("on_conflict", |lua, this, v: Value| {
+            this.check_not_added("on_conflict")?;
+            this.on_conflict = lua.from_value(v)?;
             Ok(())
         });
+        fields.add_field_method_get("on_conflict", |lua, this| lua.to_value(&this.on_conflict));
         fields.add_meta_field_with("__name", |_lua| Ok("LuaScript".to_string()));
     }
 }
 #[derive(Default, Debug)]
 pub struct ScriptsManager {
     pub scripts: Vec<LuaScript>,
-    pub fns: HashMap<String, Option<RegistryKey>>,
+    pub fns: HashMap<String, Arc<RegistryKey>>,
+    // hash and file of the source currently being loaded, given to every luaScript it creates
+    pub loading_hash: String,
+    pub loading_file: Option<PathBuf>,
+    // set while the built-in scripts load, their names get the `builtin:` prefix
+    pub loading_builtin: bool,
+    // names of the scripts currently running, outermost first
+    pub call_stack: Vec<String>,
+}
+
+impl ScriptsManager {
+    pub fn get_fn<'lua>(&self, lua: &'lua Lua, name: &str) -> Option<Function<'lua>> {
+        self.fns
+            .get(name)
+            .and_then(|key| lua.registry_value::<Function>(key).ok())
+    }
+
+    /// the script called `name`, or the built-in one when no user script has that name
+    pub fn resolve(&self, name: &str) -> Option<&LuaScript> {
+        self.scripts
+            .iter()
+            .find(|s| s.name == name)
+            .or_else(|| {
+                let name = format!("{}{}", PREFIX, name);
+                self.scripts.iter().find(|s| s.name == name)
+            })
+    }
+
+    /// records `name` as running, refusing cycles and overly deep nesting
+    pub fn enter(&mut self, name: &str) -> mlua::Result<()> {
+        if self.call_stack.iter().any(|x| x == name) {
+            return Err(mlua::Error::RuntimeError(format!(
+                "script cycle detected : {} -> {}",
+                self.call_stack.join(" -> "),
+                name
+            )));
+        }
+        if self.call_stack.len() >= MAX_CALL_DEPTH {
+            return Err(mlua::Error::RuntimeError(format!(
+                "script call depth limit ({}) reached when running \"{}\"",
+                MAX_CALL_DEPTH, name
+            )));
+        }
+        self.call_stack.push(name.to_string());
+        Ok(())
+    }
+
+    pub fn exit(&mut self) {
+        self.call_stack.pop();
+    }
 }
 
 impl UserData for ScriptsManager {
     fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
-        methods.add_method_mut("add", |_l, this, s: LuaScript| {
-            // if !s.is::<LuaScript>() {
-            //     return Err(mlua::Error::RuntimeError("Expected LuaScript".to_string()));
-            // }
+        methods.add_method_mut("add", |_l, this, ud: AnyUserData| {
+            let mut s = {
+                let script = ud.borrow_mut::<LuaScript>()?;
+                script.check_not_added("scriptManager:add")?;
+                script.clone()
+            };
+            if s.file.is_some() && s.name.starts_with(PREFIX) {
+                return Err(mlua::Error::RuntimeError(format!(
+                    "script \"{}\" can't be added, names starting with \"{}\" are reserved",
+                    s.name, PREFIX
+                )));
+            }
+            if let Some(existing) = this.scripts.iter().find(|x| x.name == s.name) {
+                let describe = |f: &Option<PathBuf>| {
+                    f.as_ref()
+                        .map_or("proj itself".to_string(), |f| f.display().to_string())
+                };
+                return Err(mlua::Error::RuntimeError(format!(
+                    "script \"{}\" is defined in both {} and {}, only the first one is used",
+                    s.name,
+                    describe(&existing.file),
+                    describe(&s.file)
+                )));
+            }
 
+            if let Some(min) = &s.min_proj_version {
+                let current = Version::parse(env!("CARGO_PKG_VERSION")).unwrap();
+                if Version::parse(min).map_or(false, |min| min > current) {
+                    return Err(mlua::Error::RuntimeError(format!(
+                        "script \"{}\" needs proj {} or newer, this is {}",
+                        s.name, min, current
+                    )));
+                }
+            }
+            if let Some(f) = s.invoke_fn.take() {
+                this.fns.insert(s.name.clone(), f);
+            }
+            s.added = true;
+            ud.borrow_mut::<LuaScript>()?.added = true;
             this.scripts.push(s);
             Ok(())
-        })
+        });
+        // a function rather than a method so the manager isn't locked while the other script runs
+        methods.add_async_function(
+            "run",
+            |lua, (_this, name, args): (AnyUserData, String, MultiValue)| async move {
+                let name = SCRIPTS_MANAGER
+                    .lock()
+                    .unwrap()
+                    .resolve(&name)
+                    .map_or(name, |s| s.name.clone());
+                PERMISSIONS_MANAGER
+                    .lock()
+                    .unwrap()
+                    .ask_for_access(&Permission::Script(name.clone()))?;
+                let lua_fn = {
+                    let mut manager = SCRIPTS_MANAGER.lock().unwrap();
+                    let lua_fn = manager.get_fn(lua, &name).ok_or_else(|| {
+                        mlua::Error::RuntimeError(format!("unable to find script \"{}\"", name))
+                    })?;
+                    manager.enter(&name)?;
+                    lua_fn
+                };
+                let result = lua_fn.call_async::<_, MultiValue>(args).await;
+                SCRIPTS_MANAGER.lock().unwrap().exit();
+                result
+            },
+        );
     }
     fn add_fields<'lua, F: mlua::UserDataFields<'lua, Self>>(fields: &mut F) {
         fields.add_field_method_get("scripts", |_l, t| {
@@ -0,0 +1,305 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use heck::{
+    ToKebabCase, ToLowerCamelCase, ToShoutySnakeCase, ToSnakeCase, ToTitleCase, ToUpperCamelCase,
+};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use minijinja::Environment;
+use mlua::{Error, LuaSerdeExt, UserData, Value};
+use path_absolutize::Absolutize;
+
+use super::{
+    effects,
+    fs::{is_path_allowed, write_file},
+    permissions::FsAccess,
+};
+
+/// the `template` global.
+/// `{% include %}` and `renderFile` look templates up relative to the script's directory.
+pub struct LuaTemplate {
+    pub script_dir: PathBuf,
+    pub project_dir: PathBuf,
+}
+
+fn template_err(e: minijinja::Error) -> Error {
+    Error::ExternalError(Arc::new(e))
+}
+
+/// a template environment with the case filters, includes are looked up in `root`
+pub fn environment(root: &Path) -> Environment<'static> {
+    let mut env = Environment::new();
+    env.set_loader(minijinja::path_loader(root.to_path_buf()));
+    env.add_filter("snake_case", |s: String| s.to_snake_case());
+    env.add_filter("pascal_case", |s: String| s.to_upper_camel_case());
+    env.add_filter("camel_case", |s: String| s.to_lower_camel_case());
+    env.add_filter("kebab_case", |s: String| s.to_kebab_case());
+    env.add_filter("shouty_snake_case", |s: String| s.to_shouty_snake_case());
+    env.add_filter("title_case", |s: String| s.to_title_case());
+    env
+}
+
+/// text which isn't utf-8 can't be rendered, so anything else counts as binary
+fn is_binary(content: &[u8]) -> bool {
+    content.iter().take(8000).any(|b| *b == 0) || std::str::from_utf8(content).is_err()
+}
+
+/// what a tree copy does with binary files
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryFiles {
+    Copy,
+    Skip,
+}
+
+/// where `path` (under `src`) ends up under `dst`, with `{{ }}` in its name rendered
+fn target_path(env: &Environment, src: &Path, dst: &Path, path: &Path, vars: &serde_json::Value) -> mlua::Result<PathBuf> {
+    let mut target = dst.to_path_buf();
+    for part in path.strip_prefix(src).unwrap_or(path).iter() {
+        target.push(env.render_str(&part.to_string_lossy(), vars).map_err(template_err)?);
+    }
+    Ok(target)
+}
+
+/// renders every file under `src` into `dst`, names included, dirs are created even when empty.
+/// permissions and mtimes are kept, returns the files written.
+fn render_tree(
+    env: &Environment,
+    src: &Path,
+    dst: &Path,
+    vars: &serde_json::Value,
+    ignored: &GlobSet,
+    binary: BinaryFiles,
+) -> mlua::Result<Vec<String>> {
+    let mut written = Vec::new();
+    let mut stack = vec![src.to_path_buf()];
+    while let Some(dir) = stack.pop() {
+        // checked per dir, a symlink could point anywhere
+        is_path_allowed(dir.as_path(), FsAccess::Read)?;
+        let target_dir = target_path(env, src, dst, &dir, vars)?;
+        is_path_allowed(target_dir.as_path(), FsAccess::Write)?;
+        effects::create_dir(&target_dir, true)?;
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let relative = path.strip_prefix(src).unwrap_or(&path);
+            if ignored.is_match(relative) {
+                continue;
+            }
+            if path.is_dir() {
+                stack.push(path);
+                continue;
+            }
+            is_path_allowed(path.as_path(), FsAccess::Read)?;
+            let content = fs::read(&path)?;
+            let target = target_path(env, src, dst, &path, vars)?;
+            if !is_binary(&content) {
+                let text = String::from_utf8_lossy(&content);
+                write_file(&target, env.render_str(&text, vars).map_err(template_err)?.as_bytes())?;
+            } else if binary == BinaryFiles::Copy {
+                write_file(&target, &content)?;
+            } else {
+                continue;
+            }
+            effects::copy_metadata(&path, &target)?;
+            written.push(target.display().to_string());
+        }
+    }
+    Ok(written)
+}
+
+/// copies `src` to `dst` with `render_tree`, includes are looked up in `src`.
+/// paths matching `ignore` (or `.templateignore`) are left out.
+pub fn copy_template(
+    src: &Path,
+    dst: &Path,
+    vars: &serde_json::Value,
+    ignore: &[String],
+    binary: BinaryFiles,
+) -> mlua::Result<Vec<String>> {
+    let env = environment(src);
+    let mut patterns = ignore.to_vec();
+    if let Ok(content) = fs::read_to_string(src.join(".templateignore")) {
+        patterns.extend(
+            content
+                .lines()
+                .map(|l| l.trim())
+                .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                .map(|l| l.to_string()),
+        );
+    }
+    patterns.push(".templateignore".to_string());
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns.iter() {
+        builder.add(Glob::new(pattern).map_err(|e| Error::ExternalError(Arc::new(e)))?);
+    }
+    let ignored = builder.build().map_err(|e| Error::ExternalError(Arc::new(e)))?;
+    render_tree(&env, src, dst, vars, &ignored, binary)
+}
+
+impl LuaTemplate {
+    fn environment(&self) -> Environment<'static> {
+        environment(&self.script_dir)
+    }
+
+    /// relative paths are taken from the script's directory
+    fn source_path(&self, p: &str) -> mlua::Result<PathBuf> {
+        let path = Path::new(p);
+        let path = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            self.script_dir.join(path)
+        };
+        Ok(path.absolutize()?.to_path_buf())
+    }
+
+    pub fn render_str(&self, env: &Environment, source: &str, vars: &serde_json::Value) -> mlua::Result<String> {
+        env.render_str(source, vars).map_err(template_err)
+    }
+
+    /// renders every file under `src` into `dst` with `render_tree`, binary files are copied as they are
+    pub fn render_dir(&self, env: &Environment, src: &Path, dst: &Path, vars: &serde_json::Value) -> mlua::Result<Vec<String>> {
+        render_tree(env, src, dst, vars, &GlobSet::empty(), BinaryFiles::Copy)
+    }
+}
+
+impl UserData for LuaTemplate {
+    fn add_fields<'lua, F: mlua::UserDataFields<'lua, Self>>(fields: &mut F) {
+        fields.add_meta_field_with("__name", |_lua| Ok("LuaTemplate".to_string()));
+    }
+
+    fn add_methods<'lua, M: mlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_method("render", |l, t, (source, vars): (String, Value)| {
+            let vars = l.from_value::<serde_json::Value>(vars)?;
+            t.render_str(&t.environment(), &source, &vars)
+        });
+        methods.add_method("renderFile", |l, t, (p, vars): (String, Value)| {
+            let path = t.source_path(&p)?;
+            is_path_allowed(path.as_path(), FsAccess::Read)?;
+            let vars = l.from_value::<serde_json::Value>(vars)?;
+            let source = fs::read_to_string(&path)?;
+            t.render_str(&t.environment(), &source, &vars)
+        });
+        // renders a whole tree onto the project dir (or `dst`), returns the files written
+        methods.add_method(
+            "renderDir",
+            |l, t, (p, vars, dst): (String, Value, Option<String>)| {
+                let src = t.source_path(&p)?;
+                let dst = match dst {
+                    Some(dst) => Path::new(&dst).absolutize()?.to_path_buf(),
+                    None => t.project_dir.clone(),
+                };
+                let vars = l.from_value::<serde_json::Value>(vars)?;
+                t.render_dir(&t.environment(), &src, &dst, &vars)
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mlua::Lua;
+    use serde_json::json;
+
+    use super::*;
+    use crate::lua::structures::{
+        conflicts::ConflictPolicy, effects::tests::GLOBAL, permissions::tests::Granted,
+    };
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("proj-template-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn case_filters() {
+        let env = environment(Path::new("."));
+        let vars = json!({"name": "my cool app"});
+        assert_eq!(
+            env.render_str("{{ name | snake_case }} {{ name | pascal_case }} {{ name | kebab_case }}", &vars).unwrap(),
+            "my_cool_app MyCoolApp my-cool-app"
+        );
+        assert_eq!(
+            env.render_str("{{ name | shouty_snake_case }} {{ name | camel_case }}", &vars).unwrap(),
+            "MY_COOL_APP myCoolApp"
+        );
+    }
+
+    #[test]
+    fn files_include_from_the_script_dir() {
+        let dir = temp_dir("include");
+        let _granted = Granted::dir(&dir);
+        fs::write(dir.join("header.txt"), "# {{ name }}").unwrap();
+        fs::write(dir.join("readme.md"), "{% include \"header.txt\" %}\nhello").unwrap();
+        let lua = Lua::new();
+        lua.globals()
+            .set("template", LuaTemplate { script_dir: dir.clone(), project_dir: dir.join("out") })
+            .unwrap();
+        let rendered: String = lua
+            .load(r#"return template:renderFile("readme.md", { name = "app" })"#)
+            .eval()
+            .unwrap();
+        assert_eq!(rendered, "# app\nhello");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn trees_keep_empty_dirs_and_binary_files() {
+        let _global = GLOBAL.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = temp_dir("tree");
+        let _granted = Granted::dir(&dir);
+        let src = dir.join("src");
+        fs::create_dir_all(src.join("{{ name }}/empty")).unwrap();
+        fs::write(src.join("{{ name }}/main.rs"), "// {{ name }}").unwrap();
+        fs::write(src.join("logo.png"), b"\x89PNG\0{{ name }}").unwrap();
+
+        let dst = dir.join("out");
+        let template = LuaTemplate { script_dir: src.clone(), project_dir: dst.clone() };
+        let mut written = template
+            .render_dir(&template.environment(), &src, &dst, &json!({"name": "app"}))
+            .unwrap();
+        effects::commit(ConflictPolicy::Overwrite).unwrap();
+        written.sort();
+        assert_eq!(
+            written,
+            vec![dst.join("app/main.rs").display().to_string(), dst.join("logo.png").display().to_string()]
+        );
+        assert_eq!(fs::read_to_string(dst.join("app/main.rs")).unwrap(), "// app");
+        assert_eq!(fs::read(dst.join("logo.png")).unwrap(), b"\x89PNG\0{{ name }}");
+        assert!(dst.join("app/empty").is_dir());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn copies_leave_out_ignored_and_binary_files() {
+        let _global = GLOBAL.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = temp_dir("copy");
+        let _granted = Granted::dir(&dir);
+        let src = dir.join("src");
+        fs::create_dir_all(src.join("target")).unwrap();
+        fs::write(src.join("target/out.txt"), "built").unwrap();
+        fs::write(src.join("notes.md"), "private").unwrap();
+        fs::write(src.join("{{ name }}.toml"), "name = \"{{ name }}\"").unwrap();
+        fs::write(src.join("logo.png"), b"\x89PNG\0").unwrap();
+        fs::write(src.join(".templateignore"), "# not copied\n*.md\n").unwrap();
+
+        let dst = dir.join("out");
+        let vars = json!({"name": "app"});
+        let written = copy_template(&src, &dst, &vars, &["target/**".to_string()], BinaryFiles::Skip).unwrap();
+        effects::commit(ConflictPolicy::Overwrite).unwrap();
+        assert_eq!(written, vec![dst.join("app.toml").display().to_string()]);
+        assert_eq!(fs::read_to_string(dst.join("app.toml")).unwrap(), "name = \"app\"");
+        for left_out in ["notes.md", "logo.png", ".templateignore", "target/out.txt"] {
+            assert!(!dst.join(left_out).exists(), "{} was copied", left_out);
+        }
+
+        let written = copy_template(&src, &dst, &vars, &["target/**".to_string()], BinaryFiles::Copy).unwrap();
+        effects::commit(ConflictPolicy::Overwrite).unwrap();
+        assert_eq!(written.len(), 2);
+        assert_eq!(fs::read(dst.join("logo.png")).unwrap(), b"\x89PNG\0");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
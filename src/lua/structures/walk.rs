@@ -0,0 +1,248 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use ignore::{
+    gitignore::{Gitignore, GitignoreBuilder},
+    Match,
+};
+use mlua::Error;
+use serde::{Deserialize, Serialize};
+
+use super::{fs::is_path_allowed, permissions::FsAccess};
+
+/// the options table of `fs:walk`, patterns are matched against the path relative to the root
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default)]
+pub struct WalkOptions {
+    // only entries matching one of these are returned, dirs are still walked into
+    pub include: Vec<String>,
+    // matching entries are left out, and dirs aren't walked into
+    pub exclude: Vec<String>,
+    // 1 is only the root's own entries
+    pub max_depth: Option<usize>,
+    // leave out what .gitignore files ignore, and .git itself
+    pub gitignore: bool,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct WalkEntry {
+    pub path: String,
+    // with `/` separators on every platform
+    pub relative: String,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    // 0 for dirs
+    pub size: u64,
+}
+
+fn glob_set(patterns: &[String]) -> mlua::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        // `*` stays within a path component, `**` crosses them
+        let glob = GlobBuilder::new(pattern)
+            .literal_separator(true)
+            .build()
+            .map_err(|e| Error::ExternalError(Arc::new(e)))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map_err(|e| Error::ExternalError(Arc::new(e)))
+}
+
+/// the deepest .gitignore with an opinion wins
+fn is_ignored(matchers: &[Arc<Gitignore>], path: &Path, is_dir: bool) -> bool {
+    matchers
+        .iter()
+        .rev()
+        .map(|m| m.matched(path, is_dir))
+        .find(|m| !m.is_none())
+        .map_or(false, |m| matches!(m, Match::Ignore(_)))
+}
+
+/// every entry under `root`, sorted by relative path. symlinks are listed but not followed.
+/// read access is checked on every dir before it is read.
+pub fn walk(root: &Path, options: &WalkOptions) -> mlua::Result<Vec<WalkEntry>> {
+    let include = glob_set(&options.include)?;
+    let exclude = glob_set(&options.exclude)?;
+    let mut entries = Vec::new();
+    let mut stack: Vec<(PathBuf, usize, Vec<Arc<Gitignore>>)> =
+        vec![(root.to_path_buf(), 0, vec![])];
+    while let Some((dir, depth, mut matchers)) = stack.pop() {
+        is_path_allowed(dir.as_path(), FsAccess::Read)?;
+        if options.gitignore && dir.join(".gitignore").is_file() {
+            let mut builder = GitignoreBuilder::new(&dir);
+            builder.add(dir.join(".gitignore"));
+            matchers.push(Arc::new(
+                builder
+                    .build()
+                    .map_err(|e| Error::ExternalError(Arc::new(e)))?,
+            ));
+        }
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let meta = fs::symlink_metadata(&path)?;
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            let is_dir = meta.is_dir();
+            if exclude.is_match(relative) {
+                continue;
+            }
+            if options.gitignore
+                && (entry.file_name() == ".git" || is_ignored(&matchers, &path, is_dir))
+            {
+                continue;
+            }
+            if options.include.is_empty() || include.is_match(relative) {
+                entries.push(WalkEntry {
+                    path: path.display().to_string(),
+                    relative: relative
+                        .components()
+                        .map(|c| c.as_os_str().to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/"),
+                    kind: if meta.file_type().is_symlink() {
+                        "symlink"
+                    } else if is_dir {
+                        "dir"
+                    } else {
+                        "file"
+                    },
+                    size: if is_dir { 0 } else { meta.len() },
+                });
+            }
+            if is_dir && options.max_depth.map_or(true, |max| depth + 1 < max) {
+                stack.push((path, depth + 1, matchers.clone()));
+            }
+        }
+    }
+    entries.sort_by(|a, b| a.relative.cmp(&b.relative));
+    Ok(entries)
+}
+
+/// the files matching `pattern`, walking from its longest directory without wildcards
+pub fn glob(pattern: &str) -> mlua::Result<Vec<WalkEntry>> {
+    let path = Path::new(pattern);
+    let mut base = PathBuf::new();
+    let mut rest = Vec::new();
+    for component in path.components() {
+        let part = component.as_os_str().to_string_lossy();
+        if rest.is_empty() && !part.contains(['*', '?', '[', '{']) {
+            base.push(component);
+        } else {
+            rest.push(part.to_string());
+        }
+    }
+    if rest.is_empty() {
+        // nothing to expand, the path itself if it exists
+        return match fs::symlink_metadata(&base) {
+            Ok(meta) => {
+                is_path_allowed(base.as_path(), FsAccess::Read)?;
+                Ok(vec![WalkEntry {
+                    path: base.display().to_string(),
+                    relative: String::new(),
+                    kind: if meta.is_dir() { "dir" } else { "file" },
+                    size: if meta.is_dir() { 0 } else { meta.len() },
+                }])
+            }
+            Err(_) => Ok(vec![]),
+        };
+    }
+    let max_depth = if rest.iter().any(|p| p.contains("**")) {
+        None
+    } else {
+        Some(rest.len())
+    };
+    let base = if base.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        base
+    };
+    walk(
+        &base,
+        &WalkOptions {
+            include: vec![rest.join("/")],
+            max_depth,
+            ..Default::default()
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::structures::permissions::tests::Granted;
+
+    /// a/b.rs, a/c/d.rs, a/c/e.txt, f.rs, .gitignore ignoring *.txt
+    fn tree(name: &str) -> (PathBuf, Granted) {
+        let dir = std::env::temp_dir().join(format!("proj-walk-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("a/c")).unwrap();
+        for file in ["a/b.rs", "a/c/d.rs", "a/c/e.txt", "f.rs"] {
+            fs::write(dir.join(file), "x").unwrap();
+        }
+        fs::write(dir.join(".gitignore"), "*.txt\n").unwrap();
+        let granted = Granted::dir(&dir);
+        (dir, granted)
+    }
+
+    fn relative(entries: &[WalkEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.relative.as_str()).collect()
+    }
+
+    #[test]
+    fn walk_options() {
+        let (dir, _granted) = tree("options");
+        let all = walk(&dir, &WalkOptions::default()).unwrap();
+        assert_eq!(
+            relative(&all),
+            vec![".gitignore", "a", "a/b.rs", "a/c", "a/c/d.rs", "a/c/e.txt", "f.rs"]
+        );
+        assert_eq!(all[1].kind, "dir");
+        assert_eq!(all[2].size, 1);
+
+        let options = WalkOptions {
+            include: vec!["**/*.rs".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(relative(&walk(&dir, &options).unwrap()), vec!["a/b.rs", "a/c/d.rs", "f.rs"]);
+
+        let options = WalkOptions {
+            exclude: vec!["a/c".to_string()],
+            max_depth: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(relative(&walk(&dir, &options).unwrap()), vec![".gitignore", "a", "a/b.rs", "f.rs"]);
+
+        let options = WalkOptions {
+            gitignore: true,
+            ..Default::default()
+        };
+        assert!(!relative(&walk(&dir, &options).unwrap()).contains(&"a/c/e.txt"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn glob_patterns() {
+        let (dir, _granted) = tree("glob");
+        let glob_in = |pattern: &str| {
+            glob(&dir.join(pattern).display().to_string())
+                .unwrap()
+                .into_iter()
+                .map(|e| e.path)
+                .collect::<Vec<_>>()
+        };
+        let path = |p: &str| dir.join(p).display().to_string();
+        // `*` stays within a dir
+        assert_eq!(glob_in("*.rs"), vec![path("f.rs")]);
+        assert_eq!(glob_in("a/**/*.rs"), vec![path("a/b.rs"), path("a/c/d.rs")]);
+        assert_eq!(glob_in("a/*/*.txt"), vec![path("a/c/e.txt")]);
+        assert_eq!(glob_in("f.rs"), vec![path("f.rs")]);
+        assert!(glob_in("missing.rs").is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,52 +1,104 @@
 #![feature(let_chains)]
+mod cli;
+mod config;
 mod lua;
+mod registry;
 mod utils;
 use std::{
-    fs::{create_dir_all, read_dir, File},
-    io::Read,
-    path::PathBuf,
-    sync::{Arc, Mutex},
+    fs::create_dir_all,
+    io::IsTerminal,
+    path::{Path, PathBuf},
 };
 
-use clap::Parser;
+use clap::{CommandFactory, Parser};
+use cli::{exit, Command, ConfigCommand, PermissionsCommand, ProgramArgs, RunOptions, ScriptCommand};
+use config::Config;
 use directories::ProjectDirs;
-use lua::structures::{fs::LuaFs, http::LuaHttp, scripts::SCRIPTS_MANAGER, permissions::{PERMISSIONS_MANAGER, Permission}};
-use mlua::{Function, Lua, LuaOptions, StdLib};
+use lua::structures::{conflicts::{ConflictError, ConflictPolicy}, effects::{self, EFFECTS}, fs::LuaFs, http::LuaHttp, params::{parse_raw_args, undeclared_args, validate_args}, process::LuaProcess, prompt::LuaPrompt, scripts::SCRIPTS_MANAGER, template::LuaTemplate, permissions::{FsAccess, PERMISSIONS_MANAGER, Permission, PermissionError, prompt::TerminalPrompt, store::{hash_source, PermissionStore}, policy::{PermissionPolicy, PolicyFile}}};
+use lua::load_error::LoadError;
+use mlua::{AnyUserData, Lua, LuaOptions, LuaSerdeExt, StdLib};
 use path_absolutize::Absolutize;
+use registry::{Registry, Source};
 
-#[derive(Parser)]
-#[clap(author, version, about)]
-struct ProgramArgs {
-    #[clap(short, long, parse(from_os_str), value_name = "PROJECT")]
-    pub project_path: Option<PathBuf>,
-    #[clap(short, long)]
-    pub script: Option<String>,
-    #[clap(short, long)]
-    pub list_scripts: bool,
-    #[clap(short = 'c', long)]
-    pub show_config: bool,
+/// the dirs and files every command may need
+struct Context {
+    config_dir: PathBuf,
+    scripts_path: PathBuf,
+    config: Config,
+    registry: Registry,
+    // scripts which failed to load, kept to explain a missing script
+    load_errors: Vec<LoadError>,
 }
+
 #[tokio::main]
 async fn main() {
     let cli: ProgramArgs = ProgramArgs::parse();
 
-    if !cli.list_scripts && cli.script.is_none() && !cli.show_config {
-        println!("No script specified");
+    if let Command::Completions { shell } = cli.command {
+        clap_complete::generate(
+            shell,
+            &mut ProgramArgs::command(),
+            "proj",
+            &mut std::io::stdout(),
+        );
         return;
     }
 
     let proj_dirs =
         ProjectDirs::from("com", "Pozm", "Proj").expect("Failed to get project directories");
-    let proj = proj_dirs.config_dir();
-
-    let scripts_path = proj.join("scripts");
-    let scripts_conf_path = scripts_path.join("config");
-    create_dir_all(&proj).unwrap();
+    let config_dir = proj_dirs.config_dir().to_path_buf();
+    let scripts_path = config_dir.join("scripts");
+    create_dir_all(&config_dir).unwrap();
     create_dir_all(&scripts_path).unwrap();
-    if cli.show_config {
-        println!("config can be found at {:?}", proj.absolutize().unwrap());
-        return;
-    }
+
+    let registry = match Registry::load(&config_dir, &scripts_path) {
+        Ok(registry) => registry,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(exit::FAILURE);
+        }
+    };
+    let mut ctx = Context {
+        config: Config::load(&config_dir),
+        config_dir,
+        scripts_path,
+        registry,
+        load_errors: Vec::new(),
+    };
+
+    let code = match cli.command {
+        Command::Config { command } => config_command(&ctx, command),
+        Command::Permissions { command } => permissions_command(&ctx, command),
+        Command::Script { command } => script_command(&mut ctx, command).await,
+        Command::List { tag, json } => match load_scripts(&mut ctx, cli.strict) {
+            Ok(_) => list(tag.as_deref(), json),
+            Err(code) => code,
+        },
+        Command::Info { script, json } => match load_scripts(&mut ctx, cli.strict) {
+            Ok(_) => info(&ctx, &script, json),
+            Err(code) => code,
+        },
+        Command::Run {
+            script,
+            project_path,
+            trailing_args,
+            options,
+        } => match load_scripts(&mut ctx, cli.strict) {
+            Ok(lua) => run(&ctx, &lua, &script, &project_path, &trailing_args, &options).await,
+            Err(code) => code,
+        },
+        Command::New { options } => match load_scripts(&mut ctx, cli.strict) {
+            Ok(lua) => new(&ctx, &lua, &options).await,
+            Err(code) => code,
+        },
+        Command::Completions { .. } => unreachable!(),
+    };
+    std::process::exit(code);
+}
+
+/// makes the lua state and loads every installed script into it.
+/// scripts which fail to load are reported and left out, `strict` gives up instead.
+fn load_scripts(ctx: &mut Context, strict: bool) -> Result<Lua, i32> {
     let lua = Lua::new_with(
         StdLib::BIT | StdLib::MATH | StdLib::STRING | StdLib::TABLE,
         LuaOptions::default(),
@@ -54,84 +106,564 @@ async fn main() {
     .expect("unable to make lua");
 
     lua::methods::setup_lua(&lua);
+    let mut errors = lua::builtin::load_builtins(&lua, &ctx.config);
+
+    let registry = &ctx.registry;
+    for path in registry.untracked() {
+        eprintln!(
+            "{} isn't installed and won't be loaded, add it with `proj script add`",
+            path.display()
+        );
+    }
+    for script in registry.scripts.iter() {
+        let path = registry.entry_path(script);
+        log::debug!("loading from {}", path.display());
+        let name = path.display().to_string();
+        let result = match registry.read(script) {
+            Ok(lua_code) => {
+                if hash_source(&lua_code) != script.hash {
+                    eprintln!(
+                        "{} was changed since it was installed, run `proj script update {}` to reinstall it",
+                        script.name, script.name
+                    );
+                }
+                lua::methods::load_script(&lua, lua_code, &name, Some(&path))
+            }
+            Err(e) => Err(LoadError::new(&name, e)),
+        };
+        errors.extend(result.err());
+    }
 
-    let read = read_dir(&scripts_path).expect("unable to open scripts directory");
+    let level = if strict { "error" } else { "warning" };
+    for e in errors.iter() {
+        eprintln!("{}: unable to load script, {}\n", level, e);
+    }
+    if strict && !errors.is_empty() {
+        return Err(exit::LOAD_FAILED);
+    }
+    ctx.load_errors = errors;
+    Ok(lua)
+}
+
+fn list(tag: Option<&str>, json: bool) -> i32 {
+    let manager = SCRIPTS_MANAGER.lock().unwrap();
+    let scripts = manager
+        .scripts
+        .iter()
+        .filter(|s| tag.map_or(true, |t| s.tags.iter().any(|x| x == t)))
+        .collect::<Vec<_>>();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&scripts).unwrap());
+        return exit::OK;
+    }
+    let mut rows = vec![["NAME", "VERSION", "TAGS", "PARAMS", "DESCRIPTION"]
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>()];
+    for script in scripts {
+        rows.push(vec![
+            script.name.clone(),
+            script.version.clone().unwrap_or_else(|| "-".to_string()),
+            script.tags.join(","),
+            script
+                .params
+                .iter()
+                .map(|p| p.summary())
+                .collect::<Vec<_>>()
+                .join(" "),
+            script.description.clone().unwrap_or_default(),
+        ]);
+    }
+    print!("{}", utils::table(&rows));
+    exit::OK
+}
 
-    for entry in read {
-        let entry = entry.unwrap();
-        if entry.file_type().unwrap().is_file() {
-            println!("loading from {}", entry.path().display());
-            let mut file = File::open(entry.path()).expect("unable to open file");
-            let mut lua_code = String::new();
-            file.read_to_string(&mut lua_code);
-            lua::methods::load_script(&lua, lua_code)
+fn info(ctx: &Context, name: &str, json: bool) -> i32 {
+    let manager = SCRIPTS_MANAGER.lock().unwrap();
+    let Some(script) = manager.resolve(name) else {
+        eprintln!("unable to find script \"{}\", see `proj list`", name);
+        return exit::SCRIPT_NOT_FOUND;
+    };
+    let installed = ctx
+        .registry
+        .scripts
+        .iter()
+        .find(|s| script.file.as_ref() == Some(&ctx.registry.entry_path(s)));
+    if json {
+        let mut value = serde_json::to_value(script).unwrap();
+        value["source"] = serde_json::to_value(installed.map(|s| s.source.to_string())).unwrap();
+        println!("{}", serde_json::to_string_pretty(&value).unwrap());
+        return exit::OK;
+    }
+    let mut rows = vec![vec!["name".to_string(), script.name.clone()]];
+    let fields = [
+        ("description", script.description.clone()),
+        ("version", script.version.clone()),
+        ("author", script.author.clone()),
+        ("tags", Some(script.tags.join(", ")).filter(|x| !x.is_empty())),
+        ("min proj version", script.min_proj_version.clone()),
+        ("homepage", script.homepage.clone()),
+        ("on conflict", script.on_conflict.map(|c| format!("{:?}", c).to_lowercase())),
+        ("source", installed.map(|s| s.source.to_string())),
+        ("file", script.file.as_ref().map(|f| f.display().to_string())),
+        ("hash", Some(script.hash.clone())),
+    ];
+    for (field, value) in fields {
+        if let Some(value) = value {
+            rows.push(vec![field.to_string(), value]);
         }
     }
+    print!("{}", utils::table(&rows));
+    if !script.params.is_empty() {
+        println!("params :");
+        for param in script.params.iter() {
+            println!("\t{}", param);
+        }
+    }
+    if let Some(permissions) = &script.permissions {
+        println!("permissions :");
+        for permission in permissions {
+            println!("\t{}", permission);
+        }
+    }
+    exit::OK
+}
 
-    // testing
+fn config_command(ctx: &Context, command: ConfigCommand) -> i32 {
+    let path = Config::path(&ctx.config_dir);
+    match command {
+        ConfigCommand::Path => println!("{}", ctx.config_dir.display()),
+        ConfigCommand::Show => match toml::to_string(&ctx.config) {
+            Ok(content) => {
+                println!("# {}", path.display());
+                print!("{}", content);
+            }
+            Err(e) => {
+                eprintln!("unable to show the config : {}", e);
+                return exit::FAILURE;
+            }
+        },
+        ConfigCommand::Edit => {
+            let editor = std::env::var("VISUAL")
+                .or_else(|_| std::env::var("EDITOR"))
+                .unwrap_or_else(|_| if cfg!(windows) { "notepad" } else { "vi" }.to_string());
+            match std::process::Command::new(&editor).arg(&path).status() {
+                Ok(status) if status.success() => {}
+                Ok(status) => {
+                    eprintln!("{} exited with {}", editor, status);
+                    return exit::FAILURE;
+                }
+                Err(e) => {
+                    eprintln!("unable to start {} : {}", editor, e);
+                    return exit::FAILURE;
+                }
+            }
+        }
+    }
+    exit::OK
+}
 
-    lua::methods::load_script(&lua, include_str!("../example.proj.lua").to_string());
+fn permissions_command(ctx: &Context, command: PermissionsCommand) -> i32 {
+    let mut store = PermissionStore::load(&ctx.config_dir);
+    match command {
+        PermissionsCommand::List { script } => {
+            let mut names = store.scripts.keys().collect::<Vec<_>>();
+            names.sort();
+            for name in names {
+                if script.as_ref().map_or(false, |s| s != name) {
+                    continue;
+                }
+                let stored = &store.scripts[name];
+                println!("{} :", name);
+                for p in stored.allowed.iter() {
+                    println!("\tallowed {}", p);
+                }
+                for p in stored.denied.iter() {
+                    println!("\tdenied  {}", p);
+                }
+            }
+            return exit::OK;
+        }
+        PermissionsCommand::Revoke { script, rule } => {
+            let removed = store.revoke(&script, rule.as_deref());
+            if removed == 0 {
+                eprintln!("nothing to revoke for \"{}\"", script);
+                return exit::FAILURE;
+            }
+            println!("revoked {} rule(s) of {}", removed, script);
+        }
+        PermissionsCommand::Reset => store.reset(),
+    }
+    match store.save() {
+        Ok(_) => exit::OK,
+        Err(e) => {
+            eprintln!("unable to save permissions : {}", e);
+            exit::FAILURE
+        }
+    }
+}
 
-    // end
+async fn script_command(ctx: &mut Context, command: ScriptCommand) -> i32 {
+    let registry = &mut ctx.registry;
+    let result = match command {
+        ScriptCommand::Add { source, name } => registry
+            .add(Source::parse(&source), name)
+            .await
+            .map(|s| println!("installed {} from {}", s.name, s.source)),
+        ScriptCommand::Remove { name } => registry
+            .remove(&name)
+            .map(|_| println!("removed {}", name)),
+        ScriptCommand::Update { name } => {
+            registry.update(name.as_deref()).await.map(|changed| {
+                if changed.is_empty() {
+                    println!("everything is up to date");
+                }
+                for name in changed {
+                    println!("updated {}", name);
+                }
+            })
+        }
+        ScriptCommand::List => {
+            for s in registry.scripts.iter() {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    s.name,
+                    s.version.as_deref().unwrap_or("-"),
+                    &s.hash[..12.min(s.hash.len())],
+                    s.source
+                );
+            }
+            Ok(())
+        }
+    };
+    match result {
+        Ok(_) => exit::OK,
+        Err(e) => {
+            eprintln!("{}", e);
+            exit::FAILURE
+        }
+    }
+}
 
-    let script_names = SCRIPTS_MANAGER
+/// asks which script to run and where, then runs it
+async fn new(ctx: &Context, lua: &Lua, options: &RunOptions) -> i32 {
+    if !std::io::stdin().is_terminal() {
+        eprintln!("`proj new` needs a terminal, use `proj run` instead");
+        return exit::USAGE;
+    }
+    let names = SCRIPTS_MANAGER
         .lock()
         .unwrap()
         .scripts
         .iter()
-        .map(|x| &x.name)
-        .cloned()
+        .map(|s| s.name.clone())
         .collect::<Vec<_>>();
-    if cli.list_scripts {
-        println!("loaded scripts : {}", script_names.join(", "));
-        return;
+    if names.is_empty() {
+        eprintln!("no scripts are installed, add one with `proj script add`");
+        return exit::SCRIPT_NOT_FOUND;
     }
-    if cli.project_path.is_none() {
-        println!("No project path specified");
-        return;
+    for (i, name) in names.iter().enumerate() {
+        eprintln!("  {}) {}", i + 1, name);
+    }
+    let script = loop {
+        let Some(answer) = TerminalPrompt::read_line("script : ") else {
+            return exit::USAGE;
+        };
+        match answer.parse::<usize>() {
+            Ok(i) if i >= 1 && i <= names.len() => break names[i - 1].clone(),
+            _ if names.contains(&answer) => break answer,
+            _ => eprintln!("unknown script \"{}\"", answer),
+        }
+    };
+    let project_path = match TerminalPrompt::read_line("project dir : ") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => return exit::USAGE,
+    };
+    run(ctx, lua, &script, &project_path, &[], options).await
+}
+
+/// undoes the run's changes when a panic unwinds out of the script.
+/// a panic hook can't, the code which panicked may still hold the effects lock then.
+struct RollbackOnPanic;
+
+impl Drop for RollbackOnPanic {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            eprintln!("proj crashed, rolling back");
+            for e in effects::rollback() {
+                eprintln!("unable to roll back {}", e);
+            }
+        }
     }
+}
 
-    create_dir_all(cli.project_path.as_ref().unwrap());
-
-    if let Some(script) = cli.script {
-        if !script_names.contains(&script) {
-            println!("unable to find that script, try using listing scripts")
-        } else {
-            if let Some(lua_fn) = SCRIPTS_MANAGER.lock().unwrap().fns.get(&script).unwrap() {
-                let lua_fn = lua.registry_value::<Function>(lua_fn).unwrap();
-                let proj_dir_path = cli
-                .project_path
-                .as_ref()
-                .unwrap()
-                .absolutize()
-                .unwrap();
-                let proj_dir = proj_dir_path
-                    .clone()
-                    .display()
-                    .to_string();
-
-                PERMISSIONS_MANAGER.lock().unwrap().allowed.push(Permission::Fs(proj_dir.clone()));
-                // PERMISSIONS_MANAGER.lock().unwrap().denied.push(Permission::Fs(proj_dir_path.join("..").absolutize().unwrap().clone().display().to_string())); // test
-
-                let globs = lua.globals();
-                globs
-                    .set("DIR_PROJECT", format!("{}/", proj_dir.clone()))
-                    .unwrap();
-                globs.set("fs", LuaFs()).unwrap();
-                globs
-                    .set(
-                        "http",
-                        LuaHttp(Arc::new(Mutex::new(reqwest::Client::new()))),
-                    )
-                    .unwrap();
-                globs.set("permissions", PERMISSIONS_MANAGER.clone()).unwrap();
-                match lua_fn.call_async::<_, ()>(()).await {
-                    Ok(_) => println!("done!"),
-                    Err(e) => eprintln!("error when calling script : {}", e),
+/// ctrl-c or being killed while the script runs undoes its changes
+fn install_rollback_handlers() {
+    // a separate task, the script may be blocked reading stdin
+    tokio::spawn(async {
+        #[cfg(unix)]
+        let terminated = async {
+            use tokio::signal::unix::{signal, SignalKind};
+            match (signal(SignalKind::terminate()), signal(SignalKind::hangup())) {
+                (Ok(mut term), Ok(mut hup)) => {
+                    tokio::select! {
+                        _ = term.recv() => {}
+                        _ = hup.recv() => {}
+                    }
                 }
+                _ => std::future::pending().await,
+            }
+        };
+        #[cfg(not(unix))]
+        let terminated = std::future::pending::<()>();
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => eprintln!("interrupted, rolling back"),
+            _ = terminated => eprintln!("terminated, rolling back"),
+        }
+        // kept locked until exit so the script can't change anything else
+        let mut effects = EFFECTS.lock().unwrap_or_else(|e| e.into_inner());
+        for e in effects.rollback() {
+            eprintln!("unable to roll back {}", e);
+        }
+        std::process::exit(exit::INTERRUPTED);
+    });
+}
+
+/// the first argument which is neither a param nor was asked for by a prompt
+fn unused_arg(lua: &Lua, undeclared: &[String]) -> Option<String> {
+    let prompt = lua.globals().get::<_, AnyUserData>("prompt").ok()?;
+    let prompt = prompt.borrow::<LuaPrompt>().ok()?;
+    let used = prompt.used.lock().unwrap();
+    undeclared.iter().find(|name| !used.contains(*name)).cloned()
+}
+
+/// whether a refused permission is what made the script fail, lua wraps it once per callback
+fn is_permission_error(e: &mlua::Error) -> bool {
+    match e {
+        mlua::Error::ExternalError(e) => e.is::<PermissionError>(),
+        mlua::Error::CallbackError { cause, .. } => is_permission_error(cause),
+        _ => false,
+    }
+}
+
+async fn run(
+    ctx: &Context,
+    lua: &Lua,
+    script: &str,
+    project_path: &Path,
+    trailing_args: &[String],
+    options: &RunOptions,
+) -> i32 {
+    let config = &ctx.config;
+    let Some(lua_script) = SCRIPTS_MANAGER.lock().unwrap().resolve(script).cloned() else {
+        if !ctx.load_errors.is_empty() {
+            eprintln!(
+                "unable to find script \"{}\", it may be in one of the scripts which failed to load",
+                script
+            );
+            return exit::LOAD_FAILED;
+        }
+        eprintln!("unable to find script \"{}\", see `proj list`", script);
+        return exit::SCRIPT_NOT_FOUND;
+    };
+    // `builtin:` may have been added
+    let script = lua_script.name.as_str();
+    // bound first so the manager isn't kept locked while the script runs
+    let Some(lua_fn) = SCRIPTS_MANAGER.lock().unwrap().get_fn(lua, script) else {
+        eprintln!("script \"{}\" has no invoke_fn", script);
+        return exit::FAILURE;
+    };
+
+    let raw_args = match parse_raw_args(&[options.args.clone(), trailing_args.to_vec()].concat()) {
+        Ok(raw_args) => raw_args,
+        Err(e) => {
+            eprintln!("{}", e);
+            return exit::USAGE;
+        }
+    };
+    let script_args = match validate_args(&lua_script.params, &raw_args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}", e);
+            return exit::USAGE;
+        }
+    };
+    // only known to be mistakes once the script asked its questions, checked after it ran
+    let undeclared = undeclared_args(&lua_script.params, &raw_args);
+    for name in undeclared.iter() {
+        eprintln!(
+            "warning: \"{}\" isn't a parameter of {}, it has to be answered by a prompt",
+            name, script
+        );
+    }
+
+    let policy = options
+        .permission_policy
+        .or(config.permission_policy)
+        .unwrap_or_default();
+    PERMISSIONS_MANAGER.lock().unwrap().policy = policy;
+    if policy == PermissionPolicy::PolicyFile {
+        let policy_path = options
+            .policy_file
+            .clone()
+            .or(config.policy_file.clone())
+            .unwrap_or_else(|| ctx.config_dir.join("policy.toml"));
+        match PolicyFile::load(&policy_path) {
+            Ok(policy_file) => PERMISSIONS_MANAGER.lock().unwrap().apply_policy(&policy_file),
+            Err(e) => {
+                eprintln!("{}", e);
+                return exit::FAILURE;
+            }
+        }
+    }
+    let script_hash = lua_script.hash.clone();
+    let mut permission_store = PermissionStore::load(&ctx.config_dir);
+    // the policy file is the only source of truth in that mode
+    if policy != PermissionPolicy::PolicyFile
+        && let Some(stored) = permission_store.get(script, &script_hash)
+    {
+        PERMISSIONS_MANAGER.lock().unwrap().restore(stored);
+    }
+
+    let proj_dir_path = project_path.absolutize().unwrap().to_path_buf();
+    let proj_dir = proj_dir_path.display().to_string();
+
+    PERMISSIONS_MANAGER.lock().unwrap().grant(Permission::Fs(FsAccess::Write, proj_dir.clone()));
+    // templates are read from next to the script, not from anywhere in the scripts dir
+    let script_dir = lua_script
+        .file
+        .as_ref()
+        .and_then(|f| f.parent())
+        .map(|d| d.to_path_buf());
+    if let Some(dir) = &script_dir {
+        PERMISSIONS_MANAGER.lock().unwrap().grant(Permission::Fs(FsAccess::Read, dir.display().to_string()));
+    }
+
+    if let Some(manifest) = lua_script.permissions.clone() {
+        let manifest = manifest
+            .into_iter()
+            .map(|p| p.with_value(p.value().replace("$PROJECT", &proj_dir)))
+            .collect::<Vec<_>>();
+        if let Err(e) = PERMISSIONS_MANAGER.lock().unwrap().ask_for_manifest(manifest) {
+            eprintln!("the script's permissions were not granted : {}", e);
+            return exit::PERMISSION_DENIED;
+        }
+    }
+
+    EFFECTS.lock().unwrap().dry_run = options.dry_run;
+    if let Err(e) = effects::create_dir(&proj_dir_path, true) {
+        eprintln!("unable to create the project dir : {}", e);
+        return exit::FAILURE;
+    }
+
+    let globs = lua.globals();
+    globs
+        .set("DIR_PROJECT", format!("{}/", proj_dir.clone()))
+        .unwrap();
+    globs.set("fs", LuaFs(proj_dir.clone())).unwrap();
+    let http = match LuaHttp::new() {
+        Ok(http) => http,
+        Err(e) => {
+            eprintln!("unable to create the http client : {}", e);
+            return exit::FAILURE;
+        }
+    };
+    globs.set("http", http).unwrap();
+    globs.set("process", LuaProcess(proj_dir.clone())).unwrap();
+    globs
+        .set(
+            "template",
+            LuaTemplate {
+                script_dir: script_dir.unwrap_or_else(|| ctx.scripts_path.clone()),
+                project_dir: proj_dir_path.clone(),
+            },
+        )
+        .unwrap();
+    globs
+        .set(
+            "prompt",
+            LuaPrompt {
+                interactive: !options.non_interactive && std::io::stdin().is_terminal(),
+                answers: raw_args.into_iter().collect(),
+                used: Default::default(),
+            },
+        )
+        .unwrap();
+    globs.set("permissions", PERMISSIONS_MANAGER.clone()).unwrap();
+    install_rollback_handlers();
+    let _rollback_on_panic = RollbackOnPanic;
+    SCRIPTS_MANAGER.lock().unwrap().enter(script).unwrap();
+    let script_args = lua.to_value(&script_args).unwrap();
+    let chosen_policy = options
+        .on_conflict
+        .or(lua_script.on_conflict)
+        .or(config.on_conflict);
+    let mut on_conflict = chosen_policy.unwrap_or_default();
+    if options.non_interactive && on_conflict == ConflictPolicy::Interactive {
+        on_conflict = ConflictPolicy::Fail;
+    }
+    let result = match lua_fn.call_async::<_, ()>(script_args).await {
+        Ok(_) => match unused_arg(lua, &undeclared) {
+            Some(unused) => Err((
+                format!(
+                    "unknown argument \"{}\", it's neither a parameter nor asked for by a prompt",
+                    unused
+                ),
+                exit::USAGE,
+            )),
+            None => effects::commit(on_conflict).map_err(|e| {
+                let code = match e.get_ref() {
+                    Some(inner) if inner.is::<ConflictError>() => exit::CONFLICT,
+                    _ => exit::FAILURE,
+                };
+                (format!("unable to commit changes : {}", e), code)
+            }),
+        },
+        Err(e) => {
+            let code = if is_permission_error(&e) {
+                exit::PERMISSION_DENIED
             } else {
-                println!("the script you provided is broken.")
+                exit::FAILURE
+            };
+            Err((format!("error when calling script : {}", e), code))
+        }
+    };
+    let code = match result {
+        Ok(conflicts) => {
+            if !conflicts.is_empty() {
+                // nobody chose to overwrite, it only happened because there was no terminal to ask
+                if chosen_policy.is_none() && on_conflict == ConflictPolicy::Overwrite {
+                    eprintln!(
+                        "warning : overwrote {} existing file(s) changed by the script, pass --on-conflict to choose what happens",
+                        conflicts.len()
+                    );
+                }
+                println!("conflicts :");
+                for (path, resolution) in conflicts {
+                    println!("\t{} : {}", path.display(), resolution);
+                }
+            }
+            println!("done!");
+            exit::OK
+        }
+        Err((e, code)) => {
+            eprintln!("{}", e);
+            eprintln!("rolling back changes");
+            for e in effects::rollback() {
+                eprintln!("unable to roll back {}", e);
             }
+            code
+        }
+    };
+    if options.dry_run {
+        println!("{}", EFFECTS.lock().unwrap_or_else(|e| e.into_inner()).report());
+    }
+
+    if policy != PermissionPolicy::PolicyFile {
+        permission_store.set(script, &script_hash, &PERMISSIONS_MANAGER.lock().unwrap());
+        if let Err(e) = permission_store.save() {
+            eprintln!("unable to save permissions : {}", e);
         }
     }
+    code
 }
@@ -0,0 +1,426 @@
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use path_absolutize::Absolutize;
+use serde::{Deserialize, Serialize};
+
+use crate::lua::structures::permissions::store::hash_source;
+
+/// where an installed script came from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Source {
+    Path { path: PathBuf },
+    Url { url: String },
+    Git { repo: String, rev: Option<String> },
+}
+
+impl Source {
+    /// `git+<url>`, `git@...` or a url ending in `.git` is a repository, `#<rev>` pins a revision.
+    /// other urls are downloaded, anything else is a local file.
+    pub fn parse(s: &str) -> Source {
+        let (base, rev) = match s.rsplit_once('#') {
+            Some((base, rev)) => (base, Some(rev.to_string())),
+            None => (s, None),
+        };
+        if let Some(repo) = base.strip_prefix("git+") {
+            Source::Git {
+                repo: repo.to_string(),
+                rev,
+            }
+        } else if base.starts_with("git@") || base.ends_with(".git") {
+            Source::Git {
+                repo: base.to_string(),
+                rev,
+            }
+        } else if s.starts_with("http://") || s.starts_with("https://") {
+            Source::Url { url: s.to_string() }
+        } else {
+            let path = Path::new(s);
+            Source::Path {
+                path: path
+                    .absolutize()
+                    .map(|p| p.to_path_buf())
+                    .unwrap_or_else(|_| path.to_path_buf()),
+            }
+        }
+    }
+
+    /// the file or repository name, without `.lua`, `.proj` or `.git`
+    fn default_name(&self) -> String {
+        let last = match self {
+            Source::Path { path } => path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            Source::Url { url } | Source::Git { repo: url, .. } => url
+                .trim_end_matches('/')
+                .rsplit(|c: char| c == '/' || c == ':')
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        };
+        let mut name = last.as_str();
+        for suffix in [".git", ".lua", ".proj"] {
+            name = name.strip_suffix(suffix).unwrap_or(name);
+        }
+        name.to_string()
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Path { path } => write!(f, "{}", path.display()),
+            Source::Url { url } => write!(f, "{}", url),
+            Source::Git { repo, rev: Some(rev) } => write!(f, "git+{}#{}", repo, rev),
+            Source::Git { repo, rev: None } => write!(f, "git+{}", repo),
+        }
+    }
+}
+
+/// an entry of the lockfile
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LockedScript {
+    pub name: String,
+    // the git revision, unknown for plain files
+    pub version: Option<String>,
+    // sha256 of the entry file when it was installed
+    pub hash: String,
+    // the file which gets loaded, relative to the scripts dir
+    pub entry: PathBuf,
+    // kept last, toml wants tables after plain values
+    pub source: Source,
+}
+
+/// the installed scripts, saved at `<config>/scripts.lock`
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Registry {
+    #[serde(default)]
+    pub scripts: Vec<LockedScript>,
+    #[serde(skip)]
+    path: PathBuf,
+    // where the scripts are installed
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+fn git(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .map_err(|e| format!("unable to run git : {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "git {} failed : {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+impl Registry {
+    pub fn path(config_dir: &Path) -> PathBuf {
+        config_dir.join("scripts.lock")
+    }
+
+    pub fn load(config_dir: &Path, scripts_dir: &Path) -> Result<Self, String> {
+        let path = Self::path(config_dir);
+        let (mut registry, first_run) = match fs::read_to_string(&path) {
+            Ok(content) => (
+                toml::from_str::<Registry>(&content)
+                    .map_err(|e| format!("unable to parse {} : {}", path.display(), e))?,
+                false,
+            ),
+            Err(_) => (Registry::default(), true),
+        };
+        registry.path = path;
+        registry.dir = scripts_dir.to_path_buf();
+        if first_run {
+            registry.adopt_untracked()?;
+        }
+        Ok(registry)
+    }
+
+    /// scripts dirs from before the lockfile existed: their lua files are added where they are
+    fn adopt_untracked(&mut self) -> Result<(), String> {
+        let untracked = self.untracked();
+        for path in untracked.iter() {
+            let name = path
+                .file_stem()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            if name.starts_with('.') || self.get(&name).is_some() {
+                continue;
+            }
+            let entry = PathBuf::from(path.file_name().unwrap_or_default());
+            let source = Source::Path { path: path.clone() };
+            let script = self.lock(&name, source, entry, None)?;
+            self.scripts.push(script);
+        }
+        if !untracked.is_empty() {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let content = toml::to_string(self).map_err(|e| e.to_string())?;
+        fs::write(&self.path, content)
+            .map_err(|e| format!("unable to write {} : {}", self.path.display(), e))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&LockedScript> {
+        self.scripts.iter().find(|s| s.name == name)
+    }
+
+    pub fn entry_path(&self, script: &LockedScript) -> PathBuf {
+        self.dir.join(&script.entry)
+    }
+
+    pub fn read(&self, script: &LockedScript) -> Result<String, String> {
+        let path = self.entry_path(script);
+        fs::read_to_string(&path).map_err(|e| format!("unable to read {} : {}", path.display(), e))
+    }
+
+    /// lua files lying in the scripts dir which were never added
+    pub fn untracked(&self) -> Vec<PathBuf> {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return vec![];
+        };
+        entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_file() && p.extension().map_or(false, |e| e == "lua"))
+            .filter(|p| !self.scripts.iter().any(|s| self.entry_path(s) == *p))
+            .collect()
+    }
+
+    /// puts the script's files in place, returns the entry file and version
+    async fn fetch(&self, name: &str, source: &Source) -> Result<(PathBuf, Option<String>), String> {
+        let file = PathBuf::from(format!("{}.lua", name));
+        match source {
+            Source::Path { path } => {
+                let target = self.dir.join(&file);
+                // a file already in the scripts dir is used where it is, copying it onto itself
+                // would truncate it
+                let same = match (path.canonicalize(), target.canonicalize()) {
+                    (Ok(from), Ok(to)) => from == to,
+                    _ => false,
+                };
+                if !same {
+                    fs::copy(path, &target)
+                        .map_err(|e| format!("unable to copy {} : {}", path.display(), e))?;
+                }
+                Ok((file, None))
+            }
+            Source::Url { url } => {
+                let code = reqwest::get(url)
+                    .await
+                    .and_then(|r| r.error_for_status())
+                    .map_err(|e| format!("unable to download {} : {}", url, e))?
+                    .text()
+                    .await
+                    .map_err(|e| format!("unable to download {} : {}", url, e))?;
+                fs::write(self.dir.join(&file), code).map_err(|e| e.to_string())?;
+                Ok((file, None))
+            }
+            Source::Git { repo, rev } => {
+                let dir = self.dir.join(name);
+                let dir_str = dir.display().to_string();
+                if dir.join(".git").exists() {
+                    git(&["-C", &dir_str, "fetch", "--tags", "origin"])?;
+                    match rev {
+                        Some(rev) => git(&["-C", &dir_str, "checkout", rev])?,
+                        None => git(&["-C", &dir_str, "pull", "--ff-only"])?,
+                    };
+                } else {
+                    git(&["clone", repo, &dir_str])?;
+                    if let Some(rev) = rev {
+                        git(&["-C", &dir_str, "checkout", rev])?;
+                    }
+                }
+                let version = git(&["-C", &dir_str, "describe", "--tags", "--always"])?;
+                let entry = ["init.lua", &format!("{}.lua", name), "main.lua"]
+                    .iter()
+                    .map(|f| Path::new(name).join(f))
+                    .find(|f| self.dir.join(f).is_file())
+                    .ok_or_else(|| {
+                        format!(
+                            "{} has no init.lua, {}.lua or main.lua at its root",
+                            repo, name
+                        )
+                    })?;
+                Ok((entry, Some(version)))
+            }
+        }
+    }
+
+    fn lock(&self, name: &str, source: Source, entry: PathBuf, version: Option<String>) -> Result<LockedScript, String> {
+        let mut script = LockedScript {
+            name: name.to_string(),
+            version,
+            hash: String::new(),
+            entry,
+            source,
+        };
+        script.hash = hash_source(&self.read(&script)?);
+        Ok(script)
+    }
+
+    pub async fn add(&mut self, source: Source, name: Option<String>) -> Result<LockedScript, String> {
+        let name = name.unwrap_or_else(|| source.default_name());
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            return Err(format!("\"{}\" can't be used as a script name", name));
+        }
+        if self.get(&name).is_some() {
+            return Err(format!(
+                "\"{}\" is already installed, use `proj script update {}`",
+                name, name
+            ));
+        }
+        let (entry, version) = self.fetch(&name, &source).await?;
+        let script = self.lock(&name, source, entry, version)?;
+        self.scripts.push(script.clone());
+        self.save()?;
+        Ok(script)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<(), String> {
+        let script = self
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("\"{}\" isn't installed", name))?;
+        let result = match script.source {
+            Source::Git { .. } => fs::remove_dir_all(self.dir.join(name)),
+            _ => fs::remove_file(self.entry_path(&script)),
+        };
+        if let Err(e) = result && e.kind() != std::io::ErrorKind::NotFound {
+            return Err(format!("unable to remove {} : {}", name, e));
+        }
+        self.scripts.retain(|s| s.name != name);
+        self.save()
+    }
+
+    /// fetches the scripts again, every one when `name` is None.
+    /// returns the names of the scripts which changed.
+    /// on a failure the scripts updated until then are still saved so the lockfile matches the files.
+    pub async fn update(&mut self, name: Option<&str>) -> Result<Vec<String>, String> {
+        if let Some(name) = name && self.get(name).is_none() {
+            return Err(format!("\"{}\" isn't installed", name));
+        }
+        let mut changed = Vec::new();
+        for i in 0..self.scripts.len() {
+            let old = self.scripts[i].clone();
+            if name.map_or(false, |n| n != old.name) {
+                continue;
+            }
+            let fetched = match self.fetch(&old.name, &old.source).await {
+                Ok((entry, version)) => self.lock(&old.name, old.source.clone(), entry, version),
+                Err(e) => Err(e),
+            };
+            let new = match fetched {
+                Ok(new) => new,
+                Err(e) => {
+                    self.save()?;
+                    return Err(format!("unable to update {} : {}", old.name, e));
+                }
+            };
+            if new.hash != old.hash {
+                changed.push(new.name.clone());
+            }
+            self.scripts[i] = new;
+        }
+        self.save()?;
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources() {
+        assert_eq!(
+            Source::parse("git+https://example.com/a/tool#v1.2"),
+            Source::Git {
+                repo: "https://example.com/a/tool".to_string(),
+                rev: Some("v1.2".to_string())
+            }
+        );
+        assert_eq!(
+            Source::parse("git@github.com:a/tool.git"),
+            Source::Git {
+                repo: "git@github.com:a/tool.git".to_string(),
+                rev: None
+            }
+        );
+        assert_eq!(
+            Source::parse("https://example.com/tool.lua"),
+            Source::Url {
+                url: "https://example.com/tool.lua".to_string()
+            }
+        );
+        match Source::parse("scripts/tool.lua") {
+            Source::Path { path } => {
+                assert!(path.is_absolute());
+                assert!(path.ends_with("scripts/tool.lua"));
+            }
+            other => panic!("{:?} isn't a path", other),
+        }
+    }
+
+    #[test]
+    fn sources_display_as_they_parse() {
+        for s in ["git+https://example.com/a/tool#main", "git+https://example.com/a/tool", "https://example.com/tool.lua"] {
+            assert_eq!(Source::parse(s).to_string(), s);
+        }
+    }
+
+    #[test]
+    fn default_names() {
+        assert_eq!(Source::parse("git@github.com:a/tool.git").default_name(), "tool");
+        assert_eq!(Source::parse("https://example.com/b/tool.lua").default_name(), "tool");
+        assert_eq!(Source::parse("/scripts/tool.proj").default_name(), "tool");
+    }
+
+    #[test]
+    fn lockfile_round_trip() {
+        let registry = Registry {
+            scripts: vec![
+                LockedScript {
+                    name: "tool".to_string(),
+                    version: Some("abc123".to_string()),
+                    hash: "00ff".to_string(),
+                    entry: PathBuf::from("tool/main.lua"),
+                    source: Source::parse("git+https://example.com/a/tool#abc123"),
+                },
+                LockedScript {
+                    name: "other".to_string(),
+                    version: None,
+                    hash: "ff00".to_string(),
+                    entry: PathBuf::from("other.lua"),
+                    source: Source::parse("https://example.com/other.lua"),
+                },
+            ],
+            ..Default::default()
+        };
+        let content = toml::to_string(&registry).unwrap();
+        let parsed = toml::from_str::<Registry>(&content).unwrap();
+        assert_eq!(parsed.scripts.len(), 2);
+        assert_eq!(parsed.scripts[0].source, registry.scripts[0].source);
+        assert_eq!(parsed.scripts[0].version.as_deref(), Some("abc123"));
+        assert_eq!(parsed.scripts[1].version, None);
+        assert_eq!(parsed.scripts[1].entry, PathBuf::from("other.lua"));
+    }
+
+    #[test]
+    fn empty_lockfile() {
+        assert!(toml::from_str::<Registry>("").unwrap().scripts.is_empty());
+    }
+}
@@ -3,8 +3,66 @@ use std::{
     path::{Path, PathBuf},
 };
 
+use path_absolutize::Absolutize;
+
+/// absolute path with every symlink resolved, parts which don't exist yet are kept as is.
+pub fn resolve_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = match path.as_ref().absolutize() {
+        Ok(p) => p.to_path_buf(),
+        Err(_) => path.as_ref().to_path_buf(),
+    };
+    let mut existing = path.as_path();
+    let mut rest = Vec::new();
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            return rest.iter().rev().fold(canonical, |acc, part| acc.join(part));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name.to_os_string());
+                existing = parent;
+            }
+            _ => return path,
+        }
+    }
+}
+
+/// lines up the columns of `rows`, the first row is the header
+pub fn table(rows: &[Vec<String>]) -> String {
+    let mut widths = Vec::new();
+    for row in rows {
+        for (i, cell) in row.iter().enumerate() {
+            let len = cell.chars().count();
+            match widths.get_mut(i) {
+                Some(w) if *w < len => *w = len,
+                Some(_) => {}
+                None => widths.push(len),
+            }
+        }
+    }
+    let mut output = String::new();
+    for row in rows {
+        let line = row
+            .iter()
+            .enumerate()
+            .map(|(i, cell)| format!("{:<width$}", cell, width = widths[i]))
+            .collect::<Vec<_>>()
+            .join("  ");
+        output.push_str(line.trim_end());
+        output.push('\n');
+    }
+    output
+}
+
 // thank you https://stackoverflow.com/a/60406693, i really cba to write this simple crap rn
-pub fn copy<U: AsRef<Path>, V: AsRef<Path>>(from: U, to: V) -> Result<(), std::io::Error> {
+/// copies a dir tree, with the dir creation and file copying done by the given functions.
+/// symlinks are never walked into, they're handed to `copy_file` like files.
+pub fn copy_with<U: AsRef<Path>, V: AsRef<Path>, E: From<std::io::Error>>(
+    from: U,
+    to: V,
+    mut mkdir: impl FnMut(&Path) -> Result<(), E>,
+    mut copy_file: impl FnMut(&Path, &Path) -> Result<(), E>,
+) -> Result<(), E> {
     let mut stack = Vec::new();
     stack.push(PathBuf::from(from.as_ref()));
 
@@ -12,7 +70,7 @@ pub fn copy<U: AsRef<Path>, V: AsRef<Path>>(from: U, to: V) -> Result<(), std::i
     let input_root = PathBuf::from(from.as_ref()).components().count();
 
     while let Some(working_path) = stack.pop() {
-        println!("process: {:?}", &working_path);
+        log::debug!("process: {:?}", &working_path);
 
         // Generate a relative path
         let src: PathBuf = working_path.components().skip(input_root).collect();
@@ -24,21 +82,21 @@ pub fn copy<U: AsRef<Path>, V: AsRef<Path>>(from: U, to: V) -> Result<(), std::i
             output_root.join(&src)
         };
         if fs::metadata(&dest).is_err() {
-            println!(" mkdir: {:?}", dest);
-            fs::create_dir_all(&dest)?;
+            log::debug!(" mkdir: {:?}", dest);
+            mkdir(&dest)?;
         }
 
         for entry in fs::read_dir(working_path)? {
             let entry = entry?;
             let path = entry.path();
-            if path.is_dir() {
+            if entry.file_type()?.is_dir() {
                 stack.push(path);
             } else {
                 match path.file_name() {
                     Some(filename) => {
                         let dest_path = dest.join(filename);
                         // println!("  copy: {:?} -> {:?}", &path, &dest_path);
-                        fs::copy(&path, &dest_path)?;
+                        copy_file(&path, &dest_path)?;
                     }
                     None => {
                         // println!("failed: {:?}", path);